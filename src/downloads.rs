@@ -1,25 +1,54 @@
 pub mod ccw;
 pub mod clipcc;
 pub mod cocrea;
+pub mod direct;
 pub mod fortycode;
 pub mod gitblock;
+pub mod penguinmod;
 pub mod scratch_cn;
 pub mod scratch;
 pub mod xmw;
 
-use std::{io::Write, path::PathBuf, sync::Arc};
+use std::{
+    fs::File,
+    future::Future,
+    io::Write,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use bytes::Bytes;
-use futures::future::try_join_all;
+use clap::ValueEnum;
+use futures::{
+    future::{try_join_all, BoxFuture, FutureExt},
+    stream, StreamExt,
+};
+use indexmap::IndexMap;
 use once_cell::sync::{Lazy, OnceCell};
 use regex::Regex;
-use reqwest::{header, Client, IntoUrl, Method, RequestBuilder};
+use reqwest::{header, Client, IntoUrl, Method, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::sync::Mutex;
 
 use crate::{
     output::{Notification, OutputSender},
-    utils::sb3::{Sb3Asset, Sb3AssetKind, Sb3Reader, Sb3Writer},
+    utils::{
+        extensions::{to_data_url, CompatibilityReport, ExtensionBundle},
+        find_html_redirect,
+        lineage::{Lineage, LineageFormat, LineageNode},
+        sb2::Sb2Project,
+        sb3::{
+            canonicalize_json, read_archive_entry, Sb3Asset, Sb3AssetKind, Sb3Compression,
+            Sb3Project, Sb3Reader, Sb3Writer, Sb3WriterOptions, ZIP_MAGIC,
+        },
+    },
     Config,
 };
 
@@ -33,6 +62,14 @@ pub static USER_AGENT: &str = concat!(
     "Chrome/131.0.0.0 Safari/537.36"
 );
 pub const INVALID_PATH: &str = r#"\/:*?"<>|"#;
+/// 分页获取列表时最多请求的页数
+const PAGE_LIMIT: usize = 500;
+/// 展开列表、改编族谱时同时下载的作品数
+const LIST_CONCURRENCY: usize = 8;
+/// 解析页面跳转的最多次数，HTTP 跳转由 reqwest 另行限制
+const REDIRECT_LIMIT: usize = 5;
+
+static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
 
 #[derive(Default, Clone)]
 pub struct DownloadDescriptor {
@@ -44,16 +81,45 @@ pub struct DownloadDescriptor {
 pub struct DownloadAssetServer {
     costumes: &'static str,
     sounds: &'static str,
+    fonts: &'static str,
+    resources: &'static str,
+    /// 下载失败时改用的资源服务器
+    fallback: Option<Box<DownloadAssetServer>>,
 }
 impl DownloadAssetServer {
     pub fn same(url: &'static str) -> Self {
         Self {
             costumes: url,
             sounds: url,
+            fonts: url,
+            resources: url,
+            fallback: None,
+        }
+    }
+    /// 其它资源与造型放在同一处
+    pub fn split(costumes: &'static str, sounds: &'static str, fonts: &'static str) -> Self {
+        Self {
+            costumes,
+            sounds,
+            fonts,
+            resources: costumes,
+            fallback: None,
         }
     }
-    pub fn split(costumes: &'static str, sounds: &'static str) -> Self {
-        Self { costumes, sounds }
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            fallback: Some(Box::new(fallback)),
+            ..self
+        }
+    }
+
+    pub fn server(&self, kind: Sb3AssetKind) -> &'static str {
+        match kind {
+            Sb3AssetKind::Costume => self.costumes,
+            Sb3AssetKind::Sound => self.sounds,
+            Sb3AssetKind::Font => self.fonts,
+            Sb3AssetKind::Resource => self.resources,
+        }
     }
 
     pub async fn download_asset(
@@ -62,15 +128,31 @@ impl DownloadAssetServer {
         asset: Sb3Asset,
         context: DownloadContext,
     ) -> Result<()> {
-        let asset_server = match asset.kind {
-            Sb3AssetKind::Costume => self.costumes,
-            Sb3AssetKind::Sound => self.sounds,
-        };
-        let url = &[asset_server, &asset.md5ext].concat();
+        let mut server = Some(self);
+        let mut last_error = None;
+        while let Some(current) = server {
+            let url = [current.server(asset.kind), &asset.md5ext].concat();
+            let res = match context
+                .get(url)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+            {
+                Ok(res) => res.bytes().await,
+                Err(err) => Err(err),
+            };
 
-        let res = context.get(url).send().await?.bytes().await?;
-        writer.add_asset(&asset.md5ext, &res)?;
-        Ok(())
+            match res {
+                Ok(res) => {
+                    writer.add_asset(asset.entry(), &res)?;
+                    return Ok(());
+                }
+                Err(err) => last_error = Some(err),
+            }
+            server = current.fallback.as_deref();
+        }
+
+        Err(last_error.map_or(anyhow!("no asset server"), Into::into))
     }
 }
 
@@ -80,6 +162,124 @@ pub trait Download: Sync + Send {
 
     async fn get(&self, context: &mut DownloadContext) -> Result<()>;
     fn decode(&self, context: &mut DownloadContext) -> Result<()>;
+
+    /// 需要打包进 .sb3 的资源，社区自行引用的文件可在此追加
+    fn assets(&self, reader: &Sb3Reader) -> Result<Vec<Sb3Asset>> {
+        reader.assets()
+    }
+    /// 返回作品的最新版本号，`None` 表示该社区不保留历史版本
+    ///
+    /// 支持历史版本的下载器应在 [`Download::get`] 中读取 [`DownloadContext::version`]
+    async fn versions(&self, _context: &mut DownloadContext) -> Result<Option<usize>> {
+        Ok(None)
+    }
+    /// 返回改编自该作品的作品链接
+    async fn remixes(&self, _context: &mut DownloadContext) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+    /// 返回云变量 (名称带有 `☁ ` 前缀) 当前的值，在 [`Download::get`] 之后调用
+    async fn cloud_variables(&self, _context: &mut DownloadContext) -> Result<CloudVariables> {
+        Ok(CloudVariables::new())
+    }
+    /// 返回最多 `limit` 条评论及其回复，在 [`Download::get`] 之后调用，社区没有评论接口时返回 `None`
+    async fn comments(
+        &self,
+        _context: &mut DownloadContext,
+        _limit: usize,
+    ) -> Result<Option<Vec<Comment>>> {
+        Ok(None)
+    }
+}
+
+pub type CloudVariables = IndexMap<String, Value>;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CloudSnapshot {
+    /// 写入 project.json 中舞台的云变量，.sb2 与 --no-assets 时改为写入 .cloud.json
+    Embed,
+    /// 写入 .sb3 文件旁的 <标题>.cloud.json
+    Sidecar,
+}
+
+/// `--history` 选择的历史版本
+#[derive(Clone, Copy)]
+pub enum DownloadVersions {
+    All,
+    Single(usize),
+    Range(usize, usize),
+}
+impl DownloadVersions {
+    pub fn select(&self, latest: usize) -> RangeInclusive<usize> {
+        match *self {
+            DownloadVersions::All => 1..=latest,
+            DownloadVersions::Single(version) => version..=version.min(latest),
+            DownloadVersions::Range(start, end) => start..=end.min(latest),
+        }
+    }
+}
+impl FromStr for DownloadVersions {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parse = |version: &str| match version.trim().parse::<usize>() {
+            Ok(version) if version > 0 => Ok(version),
+            _ => Err(format!("无效的版本号: {}", version)),
+        };
+
+        match s.split_once('-') {
+            _ if s == "all" => Ok(DownloadVersions::All),
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                match start <= end {
+                    true => Ok(DownloadVersions::Range(start, end)),
+                    false => Err(format!("版本范围 {} 的起点大于终点", s)),
+                }
+            }
+            None => parse(s).map(DownloadVersions::Single),
+        }
+    }
+}
+
+/// 列出合集 (如用户主页) 中的作品
+#[async_trait::async_trait]
+pub trait DownloadList: Sync + Send {
+    fn descriptor(&self) -> DownloadDescriptor;
+
+    /// 返回作品链接，之后交给 [`DownloadManager::select`] 选择下载器
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>>;
+}
+
+/// 从第 0 页开始逐页获取，直到某一页不足 `page_size` 项
+pub async fn paginate<T, F, Fut>(page_size: usize, fetch: F) -> Result<Vec<T>>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Vec<T>>>,
+{
+    paginate_limited(page_size, usize::MAX, fetch).await
+}
+/// 与 [`paginate`] 相同，但获取到 `limit` 项后不再请求下一页
+pub async fn paginate_limited<T, F, Fut>(
+    page_size: usize,
+    limit: usize,
+    mut fetch: F,
+) -> Result<Vec<T>>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = Result<Vec<T>>>,
+{
+    let mut items = Vec::new();
+    for page in 0..PAGE_LIMIT {
+        let page_items = fetch(page).await?;
+        let len = page_items.len();
+        items.extend(page_items);
+
+        if len < page_size || items.len() >= limit {
+            break;
+        }
+    }
+    items.truncate(limit);
+
+    Ok(items)
 }
 
 #[derive(Clone, Default)]
@@ -88,8 +288,129 @@ pub struct DownloadContext {
     pub id: String,
     pub url: Option<String>,
     pub title: Option<String>,
-    pub authors: Vec<String>,
+    pub authors: Vec<Author>,
     pub buffer: Option<Bytes>,
+    /// 下载到 .sb3 压缩包时的原始文件，资源优先从中读取
+    pub archive: Option<Bytes>,
+    /// 要下载的历史版本，`None` 时下载最新版本
+    pub version: Option<usize>,
+    /// 原作品的链接，由 [`Download::get`] 填写
+    pub parent: Option<String>,
+    /// 作品封面的链接，由 [`Download::get`] 填写
+    pub thumbnail: Option<String>,
+    pub metadata: ProjectMetadata,
+}
+
+/// 作品作者的昵称与社区中的用户 ID
+#[derive(Clone, Default, Serialize)]
+pub struct Author {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+}
+impl Author {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Author {
+            name: name.into(),
+            id: None,
+        }
+    }
+    pub fn with_id<I: Into<String>>(mut self, id: Option<I>) -> Self {
+        self.id = id.map(Into::into);
+        self
+    }
+}
+
+/// 社区接口中的 ID 与时间，有的社区以字符串返回，有的以数字返回
+#[derive(Debug)]
+pub struct StringOrNumber(pub String);
+impl<'de> Deserialize<'de> for StringOrNumber {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            String(String),
+            Number(u64),
+        }
+
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::String(value) => StringOrNumber(value),
+            Raw::Number(value) => StringOrNumber(value.to_string()),
+        })
+    }
+}
+impl From<StringOrNumber> for String {
+    fn from(value: StringOrNumber) -> Self {
+        value.0
+    }
+}
+
+/// 标签有的是字符串，有的是带 `name` 的对象，其它形式的标签会被忽略
+pub fn tag_names(tags: Option<Vec<Value>>) -> Vec<String> {
+    tags.unwrap_or_default()
+        .into_iter()
+        .filter_map(|tag| match tag {
+            Value::String(name) => Some(name),
+            Value::Object(mut tag) => match tag.remove("name")? {
+                Value::String(name) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// 作品下的一条评论，`replies` 为楼中楼
+#[derive(Serialize)]
+pub struct Comment {
+    pub id: String,
+    pub author: Option<Author>,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub replies: Vec<Comment>,
+}
+
+/// 社区页面上除标题、作者外的作品信息，社区不提供的项留空
+#[derive(Clone, Default, Serialize)]
+pub struct ProjectMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Scratch 的「备注与谢志」
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub views: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub likes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorites: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remixes: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+}
+
+/// 写入 `<标题>.meta.json` 的作品信息
+#[derive(Serialize)]
+struct MetadataSidecar<'a> {
+    site: &'static str,
+    id: &'a str,
+    title: &'a Option<String>,
+    authors: &'a [Author],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: &'a Option<String>,
+    #[serde(flatten)]
+    metadata: &'a ProjectMetadata,
 }
 
 impl DownloadContext {
@@ -101,7 +422,7 @@ impl DownloadContext {
         }
     }
 
-    pub fn set_info<U: Into<String>>(&mut self, url: U, title: String, authors: Vec<String>) {
+    pub fn set_info<U: Into<String>>(&mut self, url: U, title: String, authors: Vec<Author>) {
         (self.url, self.title, self.authors) = (Some(url.into()), Some(title), authors);
     }
     pub fn set_buffer(&mut self, buffer: Bytes) {
@@ -123,9 +444,16 @@ impl DownloadContext {
     }
 }
 
+type LazyDownload = Lazy<Box<dyn Download>>;
+
 #[derive(Default)]
 pub struct DownloadManager {
-    downloaders: Vec<(Regex, Lazy<Box<dyn Download>>)>,
+    downloaders: Vec<(&'static str, Regex, LazyDownload)>,
+    lists: Vec<(Regex, Lazy<Box<dyn DownloadList>>)>,
+    /// 没有下载器匹配时使用
+    fallback: Option<(Regex, LazyDownload)>,
+    /// 把其它形式的链接改写为下载器认识的形式
+    canonical: Vec<(Regex, &'static str)>,
 }
 
 impl DownloadManager {
@@ -133,25 +461,321 @@ impl DownloadManager {
         DownloadManager::default()
     }
 
-    pub fn add(&mut self, matcher: &str, init: fn() -> Box<dyn Download>) {
+    /// `name` 是链接有歧义时展示给用户的社区名，与下载器的 `display_name` 相同
+    pub fn add(&mut self, name: &'static str, matcher: &str, init: fn() -> Box<dyn Download>) {
         self.downloaders
+            .push((name, Regex::new(matcher).unwrap(), Lazy::new(init)));
+    }
+    pub fn set_fallback(&mut self, matcher: &str, init: fn() -> Box<dyn Download>) {
+        self.fallback = Some((Regex::new(matcher).unwrap(), Lazy::new(init)));
+    }
+    /// `replacement` 中可以用 `${id}` 等引用 `matcher` 的捕获组
+    pub fn add_canonical(&mut self, matcher: &str, replacement: &'static str) {
+        self.canonical
+            .push((Regex::new(matcher).unwrap(), replacement));
+    }
+    pub fn add_list(&mut self, matcher: &str, init: fn() -> Box<dyn DownloadList>) {
+        self.lists
             .push((Regex::new(matcher).unwrap(), Lazy::new(init)));
     }
 
-    pub fn select<'a>(&'a self, source: &'a str) -> Option<Handler<'a>> {
-        self.downloaders
+    pub fn canonicalize(&self, source: &str) -> String {
+        let source = source.trim();
+
+        self.canonical
+            .iter()
+            .find(|(r, _)| r.is_match(source))
+            .map_or(source.to_owned(), |(r, replacement)| {
+                r.replace(source, *replacement).into_owned()
+            })
+    }
+
+    /// 链接同时匹配多个下载器时返回错误，没有匹配时使用后备下载器
+    fn matched(&self, source: &str) -> Result<Option<(&Regex, &LazyDownload)>> {
+        let matched = self
+            .downloaders
+            .iter()
+            .filter(|(_, r, _)| r.is_match(source))
+            .collect::<Vec<_>>();
+        if matched.len() > 1 {
+            let names = matched.iter().map(|(name, _, _)| *name).collect::<Vec<_>>();
+            return Err(anyhow!("链接同时匹配多个下载器: {}", names.join(", ")));
+        }
+
+        Ok(matched
+            .into_iter()
+            .map(|(_, r, p)| (r, p))
+            .chain(self.fallback.iter().map(|(r, p)| (r, p)))
+            .find(|(r, _)| r.is_match(source)))
+    }
+    pub fn select<'a>(&'a self, source: &str) -> Result<Option<Handler<'a>>> {
+        let handler = self.matched(source)?.map(move |(r, p)| {
+            let caps = r.captures(source).unwrap();
+            let id = caps.name("id").unwrap().as_str();
+
+            Handler::new(id, Lazy::force(p).as_ref())
+        });
+        Ok(handler)
+    }
+    pub fn select_list<'a>(&'a self, source: &str) -> Option<ListHandler<'a>> {
+        self.lists
             .iter()
             .find(|(r, _)| r.is_match(source))
             .map(move |(r, p)| {
                 let caps = r.captures(source).unwrap();
                 let id = caps.name("id").unwrap().as_str();
 
-                Handler::new(id, Lazy::force(p).as_ref())
+                ListHandler::new(id, Lazy::force(p).as_ref())
             })
     }
 
-    pub fn is_valid(&self, source: &str) -> bool {
-        self.downloaders.iter().any(|(r, _)| r.is_match(source))
+    /// 检查链接能否下载，返回改写后的链接
+    pub fn check(&self, source: &str) -> Result<String> {
+        let source = self.canonicalize(source);
+
+        // 解析命令行参数时全局配置还未初始化，不能创建 Handler
+        match self.matched(&source)? {
+            Some(_) => Ok(source),
+            None if self.select_list(&source).is_some() => Ok(source),
+            // 可能是短链接或跳转页，下载时再解析
+            None if is_http_url(&source) => Ok(source),
+            None => Err(anyhow!("没有能胜任此链接的下载器")),
+        }
+    }
+
+    /// 下载链接对应的作品，合集链接会展开为其中的每一个作品
+    pub fn dispatch(&self, source: String) -> BoxFuture<'_, ()> {
+        async move {
+            let (config, _, _) = CONTEXT.get().unwrap();
+            let source = self.canonicalize(&source);
+
+            let handler = match self.select(&source) {
+                Ok(handler) => handler,
+                Err(err) => return report_error("链接", source, err),
+            };
+
+            if let Some(mut handler) = handler {
+                if config.with_parents || config.with_remixes.is_some() {
+                    self.download_lineage(handler, source.clone()).await
+                } else if let Some(versions) = &config.history {
+                    handler.download_versions(versions).await
+                } else {
+                    handler.download(next_index()).await
+                }
+            } else if let Some(mut list) = self.select_list(&source) {
+                let sources = list.list(next_index()).await;
+                stream::iter(sources)
+                    .for_each_concurrent(LIST_CONCURRENCY, |source| self.dispatch(source))
+                    .await;
+            } else {
+                match self.resolve(&source).await {
+                    Ok(source) => self.dispatch(source).await,
+                    Err(err) => report_error("链接跳转", source, err),
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// 跟随 HTTP 跳转、`<meta http-equiv="refresh">` 与脚本中的 `location` 跳转，
+    /// 直到得到有下载器能处理的链接
+    async fn resolve(&self, source: &str) -> Result<String> {
+        let (_, client, _) = CONTEXT.get().unwrap();
+        let handled = |url: &Url| {
+            let source = self.canonicalize(url.as_str());
+            // 匹配多个下载器时同样交给 dispatch 报错
+            let found =
+                !matches!(self.matched(&source), Ok(None)) || self.select_list(&source).is_some();
+
+            found.then_some(source)
+        };
+        let mut url = Url::parse(source)?;
+
+        for _ in 0..REDIRECT_LIMIT {
+            // reqwest 会自行跟随 HTTP 跳转
+            let res = client.get(url).send().await?.error_for_status()?;
+            url = res.url().clone();
+            if let Some(source) = handled(&url) {
+                return Ok(source);
+            }
+
+            let is_html = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("html"));
+            if !is_html {
+                break;
+            }
+            let Some(next) = find_html_redirect(&res.text().await?) else {
+                break;
+            };
+            url = url.join(&next)?;
+            if let Some(source) = handled(&url) {
+                return Ok(source);
+            }
+        }
+
+        Err(anyhow!("跳转到 {} 后仍没有能胜任此链接的下载器", url))
+    }
+
+    /// 沿改编关系向上、向下下载作品，并把族谱写在作品旁边
+    async fn download_lineage(&self, mut handler: Handler<'_>, source: String) {
+        let (config, _, tx) = CONTEXT.get().unwrap();
+        let mut lineage = Lineage::default();
+
+        handler.download(next_index()).await;
+        lineage.add_node(handler.lineage_node(&source));
+        let root = handler.context.clone();
+
+        if config.with_parents {
+            let (mut child, mut parent) = (root.id.clone(), root.parent.clone());
+            while let Some(source) = parent.take() {
+                let source = self.canonicalize(&source);
+                let mut handler = match self.select(&source) {
+                    Ok(Some(handler)) => handler,
+                    Ok(None) => break,
+                    Err(err) => {
+                        report_error("原作品", source, err);
+                        break;
+                    }
+                };
+                if lineage.contains(&handler.context.id) {
+                    lineage.add_edge(&handler.context.id, &child);
+                    break;
+                }
+
+                handler.download(next_index()).await;
+                lineage.add_node(handler.lineage_node(&source));
+                lineage.add_edge(&handler.context.id, &child);
+
+                (child, parent) = (handler.context.id.clone(), handler.context.parent.clone());
+            }
+        }
+
+        let mut level = vec![handler];
+        for _ in 0..config.with_remixes.unwrap_or_default() {
+            let mut next = Vec::new();
+            for parent in level.iter_mut() {
+                for source in parent.remixes().await {
+                    let source = self.canonicalize(&source);
+                    let handler = match self.select(&source) {
+                        Ok(Some(handler)) => handler,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            report_error("改编作品", source, err);
+                            continue;
+                        }
+                    };
+                    let id = handler.context.id.clone();
+                    if !lineage.contains(&id) {
+                        lineage.add_node(handler.lineage_node(&source));
+                        next.push((source, handler));
+                    }
+                    lineage.add_edge(&parent.context.id, &id);
+                }
+            }
+
+            stream::iter(next.iter_mut())
+                .for_each_concurrent(LIST_CONCURRENCY, |(_, handler)| {
+                    handler.download(next_index())
+                })
+                .await;
+            for (source, handler) in &next {
+                lineage.add_node(handler.lineage_node(source));
+            }
+            level = next.into_iter().map(|(_, handler)| handler).collect();
+        }
+
+        let idx = next_index();
+        tx.send_single(
+            idx,
+            Notification::SelectedDownload {
+                name: "改编族谱",
+                id: root.id.clone(),
+            },
+        )
+        .unwrap();
+
+        let mut title = root.title.unwrap_or(root.id);
+        title.retain(|c| !INVALID_PATH.contains(c));
+        let path = config
+            .path
+            .join([&title, ".", config.lineage_format.extension()].concat());
+
+        let notification = match write_lineage(&lineage, config.lineage_format, path) {
+            Ok(_) => Notification::Finished,
+            Err(err) => Notification::Error(err),
+        };
+        tx.send_single(idx, notification).unwrap();
+    }
+}
+
+/// 没有交给下载器的链接出错时，单独显示一行错误
+fn report_error(name: &'static str, id: String, err: anyhow::Error) {
+    let (_, _, tx) = CONTEXT.get().unwrap();
+    let idx = next_index();
+
+    tx.send_single(idx, Notification::SelectedDownload { name, id })
+        .and_then(|_| tx.send_single(idx, Notification::Error(err)))
+        .unwrap();
+}
+
+fn write_lineage(lineage: &Lineage, format: LineageFormat, path: PathBuf) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(lineage.render(format)?.as_bytes())?;
+
+    Ok(())
+}
+
+fn is_http_url(source: &str) -> bool {
+    Url::parse(source).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+fn next_index() -> usize {
+    NEXT_INDEX.fetch_add(1, Ordering::SeqCst)
+}
+
+pub struct ListHandler<'a> {
+    lister: &'a dyn DownloadList,
+    context: DownloadContext,
+}
+impl<'a> ListHandler<'a> {
+    pub fn new(id: &str, lister: &'a dyn DownloadList) -> Self {
+        let context = DownloadContext::new(id.to_owned(), lister.descriptor());
+
+        Self { lister, context }
+    }
+
+    /// 出错时会在终端报告，并返回空列表
+    pub async fn list(&mut self, idx: usize) -> Vec<String> {
+        let (_, _, tx) = CONTEXT.get().unwrap();
+
+        match self.list_inner(idx).await {
+            Ok(sources) => sources,
+            Err(err) => {
+                tx.send_single(idx, Notification::Error(err)).unwrap();
+                Vec::new()
+            }
+        }
+    }
+
+    async fn list_inner(&mut self, idx: usize) -> Result<Vec<String>> {
+        let (_, _, tx) = CONTEXT.get().unwrap();
+
+        tx.send_single(
+            idx,
+            Notification::SelectedDownload {
+                name: self.lister.descriptor().display_name,
+                id: self.context.id.clone(),
+            },
+        )?;
+
+        let sources = self.lister.list(&mut self.context).await?;
+        tx.send_single(idx, Notification::ListedProjects(sources.len()))?;
+        tx.send_single(idx, Notification::Finished)?;
+
+        Ok(sources)
     }
 }
 
@@ -162,7 +786,7 @@ pub struct Handler<'a> {
     context: DownloadContext,
 }
 impl<'a> Handler<'a> {
-    pub fn new(id: &'a str, downloader: &'a dyn Download) -> Self {
+    pub fn new(id: &str, downloader: &'a dyn Download) -> Self {
         let context = DownloadContext::new(id.to_owned(), downloader.descriptor());
 
         Self {
@@ -181,17 +805,75 @@ impl<'a> Handler<'a> {
         }
     }
 
-    async fn download_inner(&mut self) -> Result<()> {
-        let (config, _, tx) = CONTEXT.get().unwrap();
-        let idx = self.idx.unwrap();
+    /// 依次下载选中的历史版本，不保留历史版本的社区只下载最新版本
+    pub async fn download_versions(&mut self, versions: &DownloadVersions) {
+        let latest = match self.downloader.versions(&mut self.context).await {
+            Ok(Some(latest)) => latest,
+            Ok(None) => return self.download(next_index()).await,
+            Err(err) => return self.fail(next_index(), err),
+        };
+
+        let selected = versions.select(latest);
+        if selected.is_empty() {
+            let err = anyhow!("作品只有 {} 个版本", latest);
+            return self.fail(next_index(), err);
+        }
+
+        for version in selected {
+            let mut handler = self.clone();
+            handler.context.version = Some(version);
+            handler.download(next_index()).await;
+        }
+    }
+    fn fail(&mut self, idx: usize, err: anyhow::Error) {
+        let (_, _, tx) = CONTEXT.get().unwrap();
+        self.idx = Some(idx);
+
+        self.notify_selected()
+            .and_then(|_| tx.send_single(idx, Notification::Error(err)))
+            .unwrap();
+    }
+
+    async fn remixes(&mut self) -> Vec<String> {
+        self.downloader
+            .remixes(&mut self.context)
+            .await
+            .unwrap_or_default()
+    }
+    fn lineage_node(&self, source: &str) -> LineageNode {
+        LineageNode {
+            id: self.context.id.clone(),
+            title: self.context.title.clone(),
+            source: source.to_owned(),
+        }
+    }
+
+    fn warn(&self, warning: String) -> Result<()> {
+        let (_, _, tx) = CONTEXT.get().unwrap();
+
+        tx.send_single(self.idx.unwrap(), Notification::Warn(warning))
+    }
+    fn notify_selected(&self) -> Result<()> {
+        let (_, _, tx) = CONTEXT.get().unwrap();
+        let id = match self.context.version {
+            Some(version) => format!("{} v{}", self.context.id, version),
+            None => self.context.id.clone(),
+        };
 
         tx.send_single(
-            idx,
+            self.idx.unwrap(),
             Notification::SelectedDownload {
                 name: self.downloader.descriptor().display_name,
-                id: self.context.id.clone(),
+                id,
             },
-        )?;
+        )
+    }
+
+    async fn download_inner(&mut self) -> Result<()> {
+        let (config, _, tx) = CONTEXT.get().unwrap();
+        let idx = self.idx.unwrap();
+
+        self.notify_selected()?;
 
         self.downloader.get(&mut self.context).await.and_then(|_| {
             let title = self.context.clone().title.unwrap();
@@ -201,11 +883,25 @@ impl<'a> Handler<'a> {
         self.downloader
             .decode(&mut self.context)
             .and_then(|_| tx.send_single(idx, Notification::DecodedProject))?;
+        self.unpack_archive()?;
         self.pack_sb3(config.path.clone()).await?;
         tx.send_single(idx, Notification::Finished)?;
 
         Ok(())
     }
+    /// 任何社区下载到的是压缩包 (.sb3 或 .sb2) 时只留下 project.json，
+    /// 资源之后优先从压缩包中取出，取不到时再从资源服务器下载
+    fn unpack_archive(&mut self) -> Result<()> {
+        let buffer = self.context.buffer();
+        if !buffer.starts_with(&ZIP_MAGIC) {
+            return Ok(());
+        }
+
+        let json = Sb3Reader::from_zip(&buffer)?.into_json();
+        self.context.archive = Some(buffer);
+        self.context.set_buffer(json.into());
+        Ok(())
+    }
     async fn get_buffer(&mut self) -> Result<()> {
         if self.context.buffer.is_some() {
             return Ok(());
@@ -218,53 +914,308 @@ impl<'a> Handler<'a> {
         Ok(())
     }
     async fn pack_sb3(&self, mut path: PathBuf) -> Result<()> {
-        let DownloadDescriptor { asset_server, .. } = self.downloader.descriptor();
         let (config, _, _) = CONTEXT.get().unwrap();
 
         let context = &self.context;
         let mut title = context.title.clone().unwrap();
+        if let Some(version) = context.version {
+            title = format!("{} (v{})", title, version);
+        }
         title.retain(|c| !INVALID_PATH.contains(c));
 
+        let sb2 = Sb2Project::detect(context.buffer());
         path.push(title);
-        path.set_extension(if config.no_assets { "json" } else { "sb3" });
+        path.set_extension(match (config.no_assets, &sb2) {
+            (true, _) => "json",
+            (false, Some(_)) => "sb2",
+            (false, None) => "sb3",
+        });
 
-        let mut file = std::fs::File::create(path)?;
+        if config.thumbnail {
+            if let Err(err) = self.save_thumbnail(&path).await {
+                self.warn(format!("封面下载失败，已跳过: {}", err))?;
+            }
+        }
+        if config.metadata {
+            self.save_metadata(&path)?;
+        }
+        if config.comments {
+            if let Err(err) = self.save_comments(&path).await {
+                self.warn(format!("评论获取失败，已跳过: {}", err))?;
+            }
+        }
+        let cloud = match config.cloud {
+            Some(snapshot) => match self.cloud_variables().await {
+                Ok(values) => Some((snapshot, values)),
+                Err(err) => {
+                    self.warn(format!("云变量获取失败，已跳过: {}", err))?;
+                    None
+                }
+            },
+            None => None,
+        };
+        // 只有 .sb3 的 project.json 能写入云变量的值
+        let cloud = match cloud {
+            Some((CloudSnapshot::Embed, values)) if config.no_assets || sb2.is_some() => {
+                self.warn("无法把云变量写入此格式的作品，改为写入 .cloud.json".into())?;
+                Some((CloudSnapshot::Sidecar, values))
+            }
+            cloud => cloud,
+        };
+        if let Some((CloudSnapshot::Sidecar, values)) = &cloud {
+            let file = File::create(path.with_extension("cloud.json"))?;
+            serde_json::to_writer_pretty(file, values)?;
+        }
+
+        let mut file = File::create(&path)?;
 
         if config.no_assets {
-            file.write_all(&context.buffer())?;
+            match config.canonical_json {
+                true => file.write_all(&canonicalize_json(context.buffer())?)?,
+                false => file.write_all(&context.buffer())?,
+            }
             return Ok(());
         }
 
-        let writer = Arc::new(Mutex::new(Sb3Writer::new(file)));
-        writer.lock().await.set_project_json(context.buffer())?;
+        let options = Sb3WriterOptions {
+            deterministic: config.deterministic,
+            canonical_json: config.canonical_json,
+            compression: Sb3Compression {
+                level: config.compression_level,
+                store_media: config.store_media,
+            },
+        };
+        let writer = Arc::new(Mutex::new(Sb3Writer::new(file, options)));
+
+        if let Some(mut project) = sb2 {
+            let assets = project.assign_ids();
+            writer.lock().await.set_project_json(project.to_json()?)?;
+            self.download_assets(&writer, assets).await?;
+
+            return Self::finish(writer);
+        }
 
         let reader = Sb3Reader::parse(context.buffer());
-        let assets = reader.assets()?.into_iter().map(|asset| async {
+
+        let mut project = reader.to_project()?.clone();
+        let mut modified = project.fill_md5ext();
+        modified |= self.bundle_extensions(&mut project, &writer, &path).await?;
+        if let Some((CloudSnapshot::Embed, values)) = &cloud {
+            modified |= project.set_cloud_values(values);
+        }
+
+        let transformed = config
+            .compat
+            .and_then(|compat| compat.apply(&mut project, config.target_player));
+        if let Some(report) = transformed {
+            let (_, _, tx) = CONTEXT.get().unwrap();
+            tx.send_single(self.idx.unwrap(), Notification::TransformedProject(report))?;
+            modified = true;
+        }
+
+        let project_json = match modified {
+            true => project.to_json()?.into(),
+            false => context.buffer(),
+        };
+        writer.lock().await.set_project_json(project_json)?;
+
+        if let Some(report) = CompatibilityReport::check(&project, config.target_player) {
             let (_, _, tx) = CONTEXT.get().unwrap();
-            let arc = Arc::clone(&writer);
+            tx.send_single(
+                self.idx.unwrap(),
+                Notification::WarnIncompatibleExtensions(report),
+            )?;
+        }
+
+        let assets = self.downloader.assets(&reader)?;
+        self.download_assets(&writer, assets).await?;
+
+        Self::finish(writer)
+    }
+    async fn cloud_variables(&self) -> Result<CloudVariables> {
+        let (_, _, tx) = CONTEXT.get().unwrap();
+
+        let mut context = self.context.clone();
+        let values = self.downloader.cloud_variables(&mut context).await?;
+        tx.send_single(
+            self.idx.unwrap(),
+            Notification::SavedCloudVariables(values.len()),
+        )?;
+
+        Ok(values)
+    }
+    async fn save_comments(&self, path: &Path) -> Result<()> {
+        let (config, _, tx) = CONTEXT.get().unwrap();
+
+        let limit = config.max_comments.unwrap_or(usize::MAX);
+        let mut context = self.context.clone();
+        let Some(comments) = self.downloader.comments(&mut context, limit).await? else {
+            return self.warn(format!(
+                "{} 没有评论接口，不保存评论",
+                context.descriptor.display_name
+            ));
+        };
+        tx.send_single(
+            self.idx.unwrap(),
+            Notification::SavedComments(comments.len()),
+        )?;
+
+        let file = File::create(path.with_extension("comments.json"))?;
+        serde_json::to_writer_pretty(file, &comments)?;
+        Ok(())
+    }
+    fn save_metadata(&self, path: &Path) -> Result<()> {
+        let context = &self.context;
+        let sidecar = MetadataSidecar {
+            site: context.descriptor.display_name,
+            id: &context.id,
+            title: &context.title,
+            authors: &context.authors,
+            version: context.version,
+            parent: &context.parent,
+            thumbnail: &context.thumbnail,
+            metadata: &context.metadata,
+        };
+
+        let file = File::create(path.with_extension("meta.json"))?;
+        serde_json::to_writer_pretty(file, &sidecar)?;
+        Ok(())
+    }
+    /// 把封面保存为作品旁的 `<标题>.thumbnail.<格式>`
+    async fn save_thumbnail(&self, path: &Path) -> Result<()> {
+        let Some(url) = self.context.thumbnail.clone() else {
+            return Ok(());
+        };
+
+        let res = self.context.get(&url).send().await?.error_for_status()?;
+        let content_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let extension = match content_type.split(';').next().unwrap_or_default() {
+            "image/jpeg" => "jpg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            "image/svg+xml" => "svg",
+            _ => Path::new(res.url().path())
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .filter(|ext| ["png", "jpg", "jpeg", "gif", "webp", "svg"].contains(ext))
+                .unwrap_or("png"),
+        };
+        let extension = ["thumbnail.", extension].concat();
+
+        let (_, _, tx) = CONTEXT.get().unwrap();
+        tx.send_single(self.idx.unwrap(), Notification::SavedThumbnail)?;
+
+        std::fs::write(path.with_extension(extension), res.bytes().await?)?;
+        Ok(())
+    }
+    async fn download_assets(
+        &self,
+        writer: &Arc<Mutex<Sb3Writer<File>>>,
+        assets: Vec<Sb3Asset>,
+    ) -> Result<()> {
+        let DownloadDescriptor { asset_server, .. } = self.downloader.descriptor();
+        let context = &self.context;
+
+        let assets = assets.into_iter().map(|asset| async {
+            let (_, _, tx) = CONTEXT.get().unwrap();
+            let arc = Arc::clone(writer);
             let mut writer = arc.lock().await;
 
             tx.send_single(
                 self.idx.unwrap(),
                 Notification::DownloadedAsset(asset.md5ext.clone()),
             )?;
+
+            let archived = context.archive.as_ref().and_then(|archive| {
+                read_archive_entry(archive, asset.source())
+                    .or_else(|| read_archive_entry(archive, &asset.md5ext))
+            });
+            if let Some(data) = archived {
+                writer.add_asset(asset.entry(), &data)?;
+                return Ok(());
+            }
+
             asset_server
                 .download_asset(&mut writer, asset, context.clone())
                 .await
         });
 
-        if let Some(extensions) = reader.community_extensions()? {
-            let (_, _, tx) = CONTEXT.get().unwrap();
-            tx.send_single(
-                self.idx.unwrap(),
-                Notification::WarnCommunityExtensions(extensions),
-            )?;
-        }
-
         try_join_all(assets).await?;
+        Ok(())
+    }
+    fn finish(writer: Arc<Mutex<Sb3Writer<File>>>) -> Result<()> {
+        let writer = Arc::try_unwrap(writer).map_err(|_| anyhow!("sb3 writer is still in use"))?;
+        writer.into_inner().finish()?;
 
         Ok(())
     }
+    /// 下载作品用到的社区插件源码，返回 project.json 是否被改写
+    async fn bundle_extensions(
+        &self,
+        project: &mut Sb3Project,
+        writer: &Mutex<Sb3Writer<File>>,
+        path: &Path,
+    ) -> Result<bool> {
+        let (config, _, tx) = CONTEXT.get().unwrap();
+        if config.bundle_extensions.is_none() && !config.inline_extensions {
+            return Ok(false);
+        }
+
+        let sources: Vec<(String, String)> = project
+            .extensions
+            .iter()
+            .filter_map(|id| {
+                let url = project.extension_urls.as_ref()?.get(id)?;
+
+                Some((id.clone(), url.clone()))
+            })
+            .filter(|(_, url)| url.starts_with("http"))
+            .collect();
+
+        let mut modified = false;
+        for (id, url) in sources {
+            let source = match self.fetch_extension(&url).await {
+                Ok(source) => source,
+                Err(err) => {
+                    self.warn(format!("插件 {} 下载失败，已跳过: {}", id, err))?;
+                    continue;
+                }
+            };
+            let mut name = id.clone();
+            name.retain(|c| !INVALID_PATH.contains(c));
+
+            match config.bundle_extensions {
+                Some(ExtensionBundle::Archive) => {
+                    let name = ["extensions/", &name, ".js"].concat();
+                    writer.lock().await.add_asset(&name, &source)?;
+                }
+                Some(ExtensionBundle::Sidecar) => {
+                    let dir = path.with_extension("extensions");
+                    std::fs::create_dir_all(&dir)?;
+                    std::fs::write(dir.join(format!("{}.js", name)), &source)?;
+                }
+                None => (),
+            }
+            if config.inline_extensions {
+                let urls = project.extension_urls.get_or_insert_with(Default::default);
+                urls.insert(id.clone(), to_data_url(&source));
+                modified = true;
+            }
+
+            tx.send_single(self.idx.unwrap(), Notification::BundledExtension(id))?;
+        }
+
+        Ok(modified)
+    }
+    async fn fetch_extension(&self, url: &str) -> Result<Bytes> {
+        let res = self.context.get(url).send().await?.error_for_status()?;
+
+        Ok(res.bytes().await?)
+    }
 }
 
 pub fn setup_static(config: Config, tx: OutputSender) {
@@ -272,3 +1223,33 @@ pub fn setup_static(config: Config, tx: OutputSender) {
 
     CONTEXT.get_or_init(|| (config, client, tx));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(s: &str) -> std::result::Result<DownloadVersions, String> {
+        s.parse::<DownloadVersions>()
+    }
+
+    #[test]
+    fn parse_versions() {
+        assert!(matches!(versions("all"), Ok(DownloadVersions::All)));
+        assert!(matches!(versions("3"), Ok(DownloadVersions::Single(3))));
+        assert!(matches!(versions("2-5"), Ok(DownloadVersions::Range(2, 5))));
+        assert!(matches!(versions("4-4"), Ok(DownloadVersions::Range(4, 4))));
+
+        assert!(versions("5-2").is_err());
+        assert!(versions("0").is_err());
+        assert!(versions("0-3").is_err());
+        assert!(versions("latest").is_err());
+    }
+
+    #[test]
+    fn select_clamps_to_latest() {
+        assert_eq!(DownloadVersions::All.select(4), 1..=4);
+        assert_eq!(DownloadVersions::Single(2).select(4), 2..=2);
+        assert_eq!(DownloadVersions::Range(2, 9).select(4), 2..=4);
+        assert!(DownloadVersions::Single(6).select(4).is_empty());
+    }
+}
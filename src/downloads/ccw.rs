@@ -1,16 +1,27 @@
 use crate::utils::{
     decode::{decode_base64, decode_cbc_aes, Base64Purpose},
-    sb3::Sb3Reader,
+    sb3::{project::asset_file_name, Sb3Asset, Sb3AssetKind, Sb3Reader},
 };
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
+use super::{
+    paginate, paginate_limited, tag_names, Author, CloudVariables, Comment, Download,
+    DownloadAssetServer, DownloadContext, DownloadDescriptor, DownloadList, ProjectMetadata,
+    StringOrNumber,
+};
 use anyhow::{anyhow, Result};
 use bytes::{BufMut, BytesMut};
 use rabdog_schema::schema;
 use reqwest::Method;
+use serde::Deserialize;
 
 const CCW_DETAIL_URL: &str = "https://community-web.ccw.site/creation/detail";
 const CCW_ACCESS_KEY: &str = "";
+const CCW_USER_CREATIONS_URL: &str = "https://community-web.ccw.site/creation/page";
+const CCW_REMIXES_URL: &str = "https://community-web.ccw.site/creation/remix/page";
+const CCW_PROJECT_PAGE_URL: &str = "https://www.ccw.site/detail/";
+const CCW_COMMENTS_URL: &str = "https://community-web.ccw.site/comment/page";
+const CCW_CLOUD_VARIABLES_URL: &str = "https://community-web.ccw.site/cloud-variable/list";
+const CCW_PAGE_SIZE: usize = 20;
 const BASE64_PREFIX: &str = "KzdnFCBRvq3";
 const V2_PREFIX: [u8; 8] = [55, 122, 188, 175, 9, 5, 2, 7];
 const ZIP_ARCHIEVE_PREFIX: [u8; 8] = [80, 75, 3, 4, 10, 0, 0, 0];
@@ -18,9 +29,99 @@ const ZIP_ARCHIEVE_PREFIX: [u8; 8] = [80, 75, 3, 4, 10, 0, 0, 0];
 schema! {
     CCWDetailResponse;
     body.title: String,
+    body.cover_link: Option<String>,
+    body.description: Option<String>,
+    body.view_count: Option<u64>,
+    body.like_count: Option<u64>,
+    body.favorite_count: Option<u64>,
+    body.tags: Option<Vec<serde_json::Value>>,
+    body.created_at: Option<StringOrNumber>,
+    body.updated_at: Option<StringOrNumber>,
+    body.author: Option<CCWAuthor>,
+    body.parent_creation: Option<CCWCreationRef>,
     body.creation_release.project_link: String
 }
 
+/// 改编作品记录的原作品
+#[derive(Debug, Deserialize)]
+pub struct CCWCreationRef {
+    oid: Option<String>,
+}
+
+schema! {
+    CCWCreationPageResponse;
+    body.data[].oid: String
+}
+
+schema! {
+    CCWCommentPageResponse;
+    body.data: Vec<CCWComment>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CCWComment {
+    oid: StringOrNumber,
+    #[serde(default)]
+    content: String,
+    created_at: Option<StringOrNumber>,
+    #[serde(alias = "user")]
+    commenter: Option<CCWAuthor>,
+    #[serde(default, alias = "replyList")]
+    replies: Vec<CCWComment>,
+}
+impl CCWComment {
+    fn into_comment(self) -> Comment {
+        Comment {
+            id: self.oid.into(),
+            author: self.commenter.and_then(CCWAuthor::into_author),
+            content: self.content,
+            created: self.created_at.map(Into::into),
+            replies: self.replies.into_iter().map(Self::into_comment).collect(),
+        }
+    }
+}
+
+schema! {
+    CCWCloudVariablesResponse;
+    body: Vec<CCWCloudVariable>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CCWCloudVariable {
+    name: String,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CCWAuthor {
+    #[serde(alias = "name")]
+    nickname: Option<String>,
+    #[serde(alias = "studentOid")]
+    oid: Option<StringOrNumber>,
+}
+impl CCWAuthor {
+    pub fn into_author(self) -> Option<Author> {
+        Some(Author::new(self.nickname?).with_id(self.oid))
+    }
+}
+
+/// Gandi IDE 写在 project.json 顶层 `gandi` 中的资源
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct GandiResources {
+    #[serde(default)]
+    assets: Vec<GandiAsset>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GandiAsset {
+    asset_id: Option<String>,
+    md5ext: Option<String>,
+    data_format: Option<String>,
+}
+
 #[derive(serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 struct CCWDetailPayload<'a> {
@@ -28,6 +129,37 @@ struct CCWDetailPayload<'a> {
     access_key: &'static str,
 }
 
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CCWCreationPagePayload<'a> {
+    student_oid: &'a str,
+    page: usize,
+    per_page: usize,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CCWRemixPagePayload<'a> {
+    creation_oid: &'a str,
+    page: usize,
+    per_page: usize,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CCWCommentPagePayload<'a> {
+    target_id: &'a str,
+    target_type: &'static str,
+    page: usize,
+    per_page: usize,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CCWCloudVariablesPayload<'a> {
+    creation_oid: &'a str,
+}
+
 #[derive(Default)]
 pub struct CCWDownload;
 
@@ -50,7 +182,27 @@ impl Download for CCWDownload {
             });
         let res = req.send().await?.json::<CCWDetailResponse>().await?.body;
 
-        context.set_info(res.creation_release.project_link, res.title, Vec::new());
+        let authors = res.author.and_then(CCWAuthor::into_author);
+        context.set_info(
+            res.creation_release.project_link,
+            res.title,
+            authors.into_iter().collect(),
+        );
+        context.thumbnail = res.cover_link;
+        context.parent = res
+            .parent_creation
+            .and_then(|parent| parent.oid)
+            .map(|oid| [CCW_PROJECT_PAGE_URL, &oid].concat());
+        context.metadata = ProjectMetadata {
+            description: res.description,
+            views: res.view_count,
+            likes: res.like_count,
+            favorites: res.favorite_count,
+            tags: tag_names(res.tags),
+            created: res.created_at.map(Into::into),
+            modified: res.updated_at.map(Into::into),
+            ..Default::default()
+        };
         Ok(())
     }
 
@@ -61,6 +213,146 @@ impl Download for CCWDownload {
 
         Ok(())
     }
+    fn assets(&self, reader: &Sb3Reader) -> Result<Vec<Sb3Asset>> {
+        let mut assets = reader.assets()?;
+        assets.extend(gandi_assets(reader)?);
+
+        Ok(assets)
+    }
+    async fn cloud_variables(&self, context: &mut DownloadContext) -> Result<CloudVariables> {
+        let req = context.request(Method::POST, CCW_CLOUD_VARIABLES_URL).json(
+            &CCWCloudVariablesPayload {
+                creation_oid: &context.id,
+            },
+        );
+        let res = req
+            .send()
+            .await?
+            .json::<CCWCloudVariablesResponse>()
+            .await?;
+
+        // 接口返回的名称可能不带 ☁ 前缀
+        let values = res.body.into_iter().map(|variable| {
+            let name = match variable.name.starts_with("☁ ") {
+                true => variable.name,
+                false => ["☁ ", &variable.name].concat(),
+            };
+            (name, variable.value)
+        });
+
+        Ok(values.collect())
+    }
+    async fn comments(
+        &self,
+        context: &mut DownloadContext,
+        limit: usize,
+    ) -> Result<Option<Vec<Comment>>> {
+        let context = &*context;
+
+        let comments = paginate_limited(CCW_PAGE_SIZE, limit, |page| async move {
+            let req =
+                context
+                    .request(Method::POST, CCW_COMMENTS_URL)
+                    .json(&CCWCommentPagePayload {
+                        target_id: &context.id,
+                        target_type: "CREATION",
+                        page: page + 1,
+                        per_page: CCW_PAGE_SIZE,
+                    });
+            let res = req.send().await?.json::<CCWCommentPageResponse>().await?;
+
+            Ok(res.body.data)
+        })
+        .await?;
+
+        Ok(Some(
+            comments.into_iter().map(CCWComment::into_comment).collect(),
+        ))
+    }
+    async fn remixes(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+
+        paginate(CCW_PAGE_SIZE, |page| {
+            creation_page(
+                context,
+                CCW_REMIXES_URL,
+                CCWRemixPagePayload {
+                    creation_oid: &context.id,
+                    page: page + 1,
+                    per_page: CCW_PAGE_SIZE,
+                },
+            )
+        })
+        .await
+    }
+}
+
+#[derive(Default)]
+pub struct CCWUserList;
+
+#[async_trait::async_trait]
+impl DownloadList for CCWUserList {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "共创世界用户",
+            referer: "https://www.ccw.site/",
+            ..Default::default()
+        }
+    }
+
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+
+        paginate(CCW_PAGE_SIZE, |page| {
+            creation_page(
+                context,
+                CCW_USER_CREATIONS_URL,
+                CCWCreationPagePayload {
+                    student_oid: &context.id,
+                    page: page + 1,
+                    per_page: CCW_PAGE_SIZE,
+                },
+            )
+        })
+        .await
+    }
+}
+
+/// 用户作品与改编作品共用的分页接口，返回作品链接
+async fn creation_page<P: serde::Serialize>(
+    context: &DownloadContext,
+    url: &str,
+    payload: P,
+) -> Result<Vec<String>> {
+    let req = context.request(Method::POST, url).json(&payload);
+    let res = req.send().await?.json::<CCWCreationPageResponse>().await?;
+
+    Ok(res
+        .body
+        .data
+        .into_iter()
+        .map(|creation| [CCW_PROJECT_PAGE_URL, &creation.oid].concat())
+        .collect())
+}
+
+/// 找出 Gandi IDE 额外引用的资源，这些文件与造型、声音放在同一个 CDN 上
+pub fn gandi_assets(reader: &Sb3Reader) -> Result<Vec<Sb3Asset>> {
+    let resources = match reader.to_project()?.extra.get("gandi") {
+        Some(gandi) => GandiResources::deserialize(gandi)?,
+        None => GandiResources::default(),
+    };
+    let assets = resources.assets.into_iter().filter_map(|asset| {
+        let md5ext = asset_file_name(&asset.md5ext, &asset.asset_id, &asset.data_format)?;
+
+        Some(Sb3Asset {
+            kind: Sb3AssetKind::Resource,
+            md5ext,
+            entry: None,
+            source: None,
+        })
+    });
+
+    Ok(assets.collect())
 }
 
 pub struct CCWLikeDecoder;
@@ -78,7 +370,7 @@ impl CCWLikeDecoder {
             _ => self.decode_v3(url, bytes)?,
         };
 
-        let mut project = Sb3Reader::from_zip(buf)?.0;
+        let mut project = Sb3Reader::from_zip(buf)?.into_json();
         if !project.starts_with(b"{") {
             project = self.decode_zip_content(project)?;
         }
@@ -1,5 +1,8 @@
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
-use crate::utils::{decode::decode_cbc_aes, get_next_data};
+use super::{
+    paginate, Author, Download, DownloadAssetServer, DownloadContext, DownloadDescriptor,
+    DownloadList, StringOrNumber,
+};
+use crate::utils::{decode::decode_cbc_aes, get_next_data, get_og_image};
 use anyhow::Result;
 use base64::{engine::general_purpose::STANDARD, Engine};
 use chrono::Utc;
@@ -8,6 +11,8 @@ use rsa::{pkcs8::DecodePublicKey, Pkcs1v15Encrypt, RsaPublicKey};
 
 const CLIPCC_SB3_URL: &str = "https://api.codingclip.com/v1/project/download";
 const CLIPCC_PROJECT_URL: &str = "https://codingclip.com/project/";
+const CLIPCC_USER_PROJECTS_URL: &str = "https://api.codingclip.com/v1/project/user";
+const CLIPCC_PAGE_SIZE: usize = 20;
 // clipccyydsclipccyydsclipccyydscc
 const CLIPCC_AES_KEY: [u8; 32] = [
     99, 108, 105, 112, 99, 99, 121, 121, 100, 115, 99, 108, 105, 112, 99, 99, 121, 121, 100, 115,
@@ -30,7 +35,14 @@ CP/tf54FNKZWpN+VZwIDAQAB
 schema! {
     ClipccData;
     props.page_props.project.name: String,
-    props.page_props.project.user_name: String
+    props.page_props.project.user_name: String,
+    props.page_props.project.user_id: Option<StringOrNumber>,
+    props.page_props.project.description: Option<String>
+}
+
+schema! {
+    ClipccUserProjects;
+    data.list[].id: u64
 }
 
 #[derive(Default)]
@@ -49,7 +61,7 @@ impl Download for ClipccDownload {
     async fn get(&self, context: &mut DownloadContext) -> Result<()> {
         let url = [CLIPCC_PROJECT_URL, &context.id].concat();
 
-        let res = context.get(url).send().await?.text().await?;
+        let res = context.get(&url).send().await?.text().await?;
         let data = get_next_data(&res)?;
         let json = serde_json::from_str::<ClipccData>(&data)?.props.page_props;
 
@@ -63,7 +75,10 @@ impl Download for ClipccDownload {
 
         let project_url = crate::utils::Url::parse_with_params(CLIPCC_SB3_URL, &[("keys", keys)])?;
 
-        context.set_info(project_url, json.project.name, vec![json.project.user_name]);
+        let author = Author::new(json.project.user_name).with_id(json.project.user_id);
+        context.set_info(project_url, json.project.name, vec![author]);
+        context.thumbnail = get_og_image(&res, &url);
+        context.metadata.description = json.project.description;
         Ok(())
     }
     fn decode(&self, context: &mut DownloadContext) -> Result<()> {
@@ -74,3 +89,43 @@ impl Download for ClipccDownload {
         Ok(())
     }
 }
+
+#[derive(Default)]
+pub struct ClipccUserList;
+
+#[async_trait::async_trait]
+impl DownloadList for ClipccUserList {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "Clipcc 用户",
+            referer: "https://codingclip.com/",
+            ..Default::default()
+        }
+    }
+
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+
+        paginate(CLIPCC_PAGE_SIZE, |page| async move {
+            let query = &[
+                ("userId", context.id.clone()),
+                ("page", (page + 1).to_string()),
+                ("pageSize", CLIPCC_PAGE_SIZE.to_string()),
+            ];
+            let res = context
+                .get(CLIPCC_USER_PROJECTS_URL)
+                .query(query)
+                .send()
+                .await?;
+            let json = res.json::<ClipccUserProjects>().await?;
+
+            Ok(json
+                .data
+                .list
+                .into_iter()
+                .map(|project| [CLIPCC_PROJECT_URL, &project.id.to_string()].concat())
+                .collect())
+        })
+        .await
+    }
+}
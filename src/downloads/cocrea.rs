@@ -2,8 +2,17 @@ use anyhow::Result;
 use chrono::Utc;
 use rabdog_schema::schema;
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
-use crate::{downloads::ccw::CCWLikeDecoder, utils::get_next_data};
+use super::{
+    tag_names, Author, Download, DownloadAssetServer, DownloadContext, DownloadDescriptor,
+    ProjectMetadata, StringOrNumber,
+};
+use crate::{
+    downloads::ccw::{gandi_assets, CCWLikeDecoder},
+    utils::{
+        get_next_data,
+        sb3::{Sb3Asset, Sb3Reader},
+    },
+};
 
 const COCREA_PROJECT_URL: &str = "https://www.cocrea.world/";
 
@@ -11,6 +20,14 @@ schema! {
     CocreaData;
     props.page_props.creation_data.title: String,
     props.page_props.creation_data.author.username: String,
+    props.page_props.creation_data.cover_link: Option<String>,
+    props.page_props.creation_data.description: Option<String>,
+    props.page_props.creation_data.view_count: Option<u64>,
+    props.page_props.creation_data.like_count: Option<u64>,
+    props.page_props.creation_data.favorite_count: Option<u64>,
+    props.page_props.creation_data.tags: Option<Vec<serde_json::Value>>,
+    props.page_props.creation_data.created_at: Option<StringOrNumber>,
+    props.page_props.creation_data.updated_at: Option<StringOrNumber>,
     props.page_props.creation_data.creation_release_resp.project_link: String
 }
 
@@ -42,8 +59,19 @@ impl Download for CocreaDownload {
         context.set_info(
             project_url,
             json.creation_data.title,
-            vec![json.creation_data.author.username],
+            vec![Author::new(json.creation_data.author.username)],
         );
+        context.thumbnail = json.creation_data.cover_link;
+        context.metadata = ProjectMetadata {
+            description: json.creation_data.description,
+            views: json.creation_data.view_count,
+            likes: json.creation_data.like_count,
+            favorites: json.creation_data.favorite_count,
+            tags: tag_names(json.creation_data.tags),
+            created: json.creation_data.created_at.map(Into::into),
+            modified: json.creation_data.updated_at.map(Into::into),
+            ..Default::default()
+        };
 
         Ok(())
     }
@@ -54,4 +82,10 @@ impl Download for CocreaDownload {
 
         Ok(())
     }
+    fn assets(&self, reader: &Sb3Reader) -> Result<Vec<Sb3Asset>> {
+        let mut assets = reader.assets()?;
+        assets.extend(gandi_assets(reader)?);
+
+        Ok(assets)
+    }
 }
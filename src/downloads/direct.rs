@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Result};
+use percent_encoding::percent_decode_str;
+
+use crate::utils::Url;
+
+use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor, CONTEXT};
+
+const SCRATCH_ASSET_URL: &str = "https://assets.scratch.mit.edu/";
+
+/// 直接下载 .sb3、.sb2 或 project.json 链接，没有匹配到社区时使用
+///
+/// 压缩包中的资源直接取出，只有 project.json 时从 `--asset-base` 或 Scratch 资源服务器下载资源
+pub struct DirectDownload;
+
+#[async_trait::async_trait]
+impl Download for DirectDownload {
+    fn descriptor(&self) -> DownloadDescriptor {
+        let (config, _, _) = CONTEXT.get().unwrap();
+        let asset_base = config.asset_base.as_deref().unwrap_or(SCRATCH_ASSET_URL);
+
+        DownloadDescriptor {
+            display_name: "链接",
+            referer: "",
+            asset_server: DownloadAssetServer::same(asset_base),
+        }
+    }
+    async fn get(&self, context: &mut DownloadContext) -> Result<()> {
+        let url = Url::parse(&context.id)?;
+
+        // 以文件名作为标题，project.json 则取所在目录名
+        let mut segments = url
+            .path_segments()
+            .ok_or(anyhow!("cannot be base"))?
+            .filter(|segment| !segment.is_empty())
+            .rev();
+        let file_name = segments.next().unwrap_or_default();
+        let title = match file_name.rsplit_once('.') {
+            Some(("project", _)) => segments.next().unwrap_or("project"),
+            Some((stem, _)) => stem,
+            None => file_name,
+        };
+        let title = percent_decode_str(title).decode_utf8_lossy().into_owned();
+
+        context.set_info(url, title, Vec::new());
+        Ok(())
+    }
+    fn decode(&self, _: &mut DownloadContext) -> Result<()> {
+        Ok(())
+    }
+}
@@ -3,7 +3,7 @@ use serde::Deserialize;
 
 use crate::utils::decode::{decode_cbc_aes, decode_hex};
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
+use super::{Author, Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
 
 const FORTYCODE_PROJECT_URL: &str =
     "https://service-dq726wx5-1302921490.sh.apigw.tencentcs.com/work/info";
@@ -45,7 +45,7 @@ impl Download for FortycodeDownload {
         let req = context.get(FORTYCODE_PROJECT_URL).query(&query);
         let res = req.send().await?.json::<FortycodeResponse>().await?;
         let sb3_url = crate::utils::Url::parse_with_params(FORTYCODE_SB3_URL, &query)?;
-        context.set_info(sb3_url, res.data.name, vec![res.data.nickname]);
+        context.set_info(sb3_url, res.data.name, vec![Author::new(res.data.nickname)]);
 
         Ok(())
     }
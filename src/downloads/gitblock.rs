@@ -1,13 +1,23 @@
 use anyhow::{ensure, Result};
 use rabdog_schema::schema;
 use reqwest::{header, Method};
+use serde::{Deserialize, Serialize};
 
 use crate::utils::decode::decode_cbc_aes;
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
+use super::{
+    paginate, paginate_limited, tag_names, Author, Comment, Download, DownloadAssetServer,
+    DownloadContext, DownloadDescriptor, DownloadList, ProjectMetadata, StringOrNumber,
+};
 
 const GITBLOCK_SB3_URL: &str = "https://asset.gitblock.cn/Project/download/";
 const GITBLOCK_PROJECT_URL: &str = "https://gitblock.cn/WebApi/Projects/$/Get";
+const GITBLOCK_USER_PROJECTS_URL: &str = "https://gitblock.cn/WebApi/Users/$/GetPagedProjects";
+const GITBLOCK_REMIXES_URL: &str = "https://gitblock.cn/WebApi/Projects/$/GetPagedRemixes";
+const GITBLOCK_PROJECT_PAGE_URL: &str = "https://gitblock.cn/Projects/";
+const GITBLOCK_MEDIA_URL: &str = "https://cdn.gitblock.cn/Media?name=";
+const GITBLOCK_COMMENTS_URL: &str = "https://gitblock.cn/WebApi/Comment/GetPage";
+const GITBLOCK_PAGE_SIZE: usize = 20;
 const GITBLOCK_KEY: &str = "4A9745825F24883B657AFC4E4626A0F2";
 const GITBLOCK_IV: &str = "4A9745825F24883B";
 
@@ -17,12 +27,84 @@ schema! {
     access_limit_tips: String,
     project.title: String,
     project.version: usize,
+    project.description: Option<String>,
+    project.view_count: Option<u64>,
+    project.like_count: Option<u64>,
+    project.favorite_count: Option<u64>,
+    project.thumb_id: Option<String>,
+    project.tags: Option<Vec<serde_json::Value>>,
+    project.create_time: Option<StringOrNumber>,
+    project.update_time: Option<StringOrNumber>,
+    project.remix_from_id: Option<u64>,
+    project.creator.id: Option<StringOrNumber>,
     project.creator.username: String,
 }
 
+schema! {
+    GitblockPagedProjects;
+    paged_projects.items[].id: u64
+}
+
+schema! {
+    GitblockCommentPage;
+    paged_threads.items: Vec<GitblockComment>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GitblockComment {
+    id: StringOrNumber,
+    #[serde(default)]
+    content: String,
+    create_time: Option<StringOrNumber>,
+    creator: Option<GitblockCommentCreator>,
+    #[serde(default)]
+    replies: Vec<GitblockComment>,
+}
+#[derive(Debug, Deserialize)]
+pub struct GitblockCommentCreator {
+    id: Option<StringOrNumber>,
+    username: String,
+}
+impl GitblockComment {
+    fn into_comment(self) -> Comment {
+        Comment {
+            id: self.id.into(),
+            author: self
+                .creator
+                .map(|creator| Author::new(creator.username).with_id(creator.id)),
+            content: self.content,
+            created: self.create_time.map(Into::into),
+            replies: self.replies.into_iter().map(Self::into_comment).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GitblockCommentPayload<'a> {
+    for_type: &'static str,
+    for_id: &'a str,
+    page_index: usize,
+    page_size: usize,
+}
+
 #[derive(Default)]
 pub struct GitblockDownload;
 
+impl GitblockDownload {
+    async fn project(&self, context: &DownloadContext) -> Result<GitblockResponse> {
+        let project_url = GITBLOCK_PROJECT_URL.replace("$", &context.id);
+        let response = context
+            .request(Method::POST, project_url)
+            .header(header::CONTENT_LENGTH, 0)
+            .send()
+            .await?;
+
+        Ok(response.json::<GitblockResponse>().await?)
+    }
+}
+
 #[async_trait::async_trait]
 impl Download for GitblockDownload {
     fn descriptor(&self) -> DownloadDescriptor {
@@ -36,29 +118,89 @@ impl Download for GitblockDownload {
     }
 
     async fn get(&self, context: &mut DownloadContext) -> Result<()> {
-        let project_url = GITBLOCK_PROJECT_URL.replace("$", &context.id);
-        let response = context
-            .request(Method::POST, project_url)
-            .header(header::CONTENT_LENGTH, 0)
-            .send()
-            .await?;
-        let json = response.json::<GitblockResponse>().await?;
+        let json = self.project(context).await?;
 
         // 该作品有亿点大，暂时被限流。升级VIP或通过2级真人认证后可以访问
         ensure!(json.access_limit_level <= 1, json.access_limit_tips);
 
+        let version = context.version.unwrap_or(json.project.version);
         let sb3_url = crate::utils::Url::parse_with_params(
             GITBLOCK_SB3_URL,
-            &[("id", context.id.as_str()), ("v", &json.project.version.to_string())],
+            &[("id", context.id.as_str()), ("v", &version.to_string())],
         )?;
 
         context.set_info(
             sb3_url,
             json.project.title,
-            vec![json.project.creator.username],
+            vec![Author::new(json.project.creator.username).with_id(json.project.creator.id)],
         );
+        context.thumbnail = json
+            .project
+            .thumb_id
+            .map(|id| [GITBLOCK_MEDIA_URL, &id].concat());
+        // 不是改编作品时为 0
+        context.parent = json
+            .project
+            .remix_from_id
+            .filter(|id| *id != 0)
+            .map(|id| [GITBLOCK_PROJECT_PAGE_URL, &id.to_string()].concat());
+        context.metadata = ProjectMetadata {
+            description: json.project.description,
+            views: json.project.view_count,
+            likes: json.project.like_count,
+            favorites: json.project.favorite_count,
+            tags: tag_names(json.project.tags),
+            created: json.project.create_time.map(Into::into),
+            modified: json.project.update_time.map(Into::into),
+            ..Default::default()
+        };
         Ok(())
     }
+    async fn comments(
+        &self,
+        context: &mut DownloadContext,
+        limit: usize,
+    ) -> Result<Option<Vec<Comment>>> {
+        let context = &*context;
+
+        let comments = paginate_limited(GITBLOCK_PAGE_SIZE, limit, |page| async move {
+            let response = context
+                .request(Method::POST, GITBLOCK_COMMENTS_URL)
+                .json(&GitblockCommentPayload {
+                    for_type: "Project",
+                    for_id: &context.id,
+                    page_index: page + 1,
+                    page_size: GITBLOCK_PAGE_SIZE,
+                })
+                .send()
+                .await?;
+            let json = response.json::<GitblockCommentPage>().await?;
+
+            Ok(json.paged_threads.items)
+        })
+        .await?;
+
+        Ok(Some(
+            comments
+                .into_iter()
+                .map(GitblockComment::into_comment)
+                .collect(),
+        ))
+    }
+    async fn versions(&self, context: &mut DownloadContext) -> Result<Option<usize>> {
+        let json = self.project(context).await?;
+
+        Ok(Some(json.project.version))
+    }
+    async fn remixes(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+        let url = GITBLOCK_REMIXES_URL.replace("$", &context.id);
+
+        paginate(GITBLOCK_PAGE_SIZE, |page| {
+            paged_projects(context, &url, page)
+        })
+        .await
+    }
 
     fn decode(&self, context: &mut DownloadContext) -> Result<()> {
         let result = decode_cbc_aes(&context.buffer(), GITBLOCK_KEY, GITBLOCK_IV)?;
@@ -68,3 +210,46 @@ impl Download for GitblockDownload {
         Ok(())
     }
 }
+
+#[derive(Default)]
+pub struct GitblockUserList;
+
+#[async_trait::async_trait]
+impl DownloadList for GitblockUserList {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "稽木世界用户",
+            referer: "https://gitblock.cn",
+            ..Default::default()
+        }
+    }
+
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+        let url = GITBLOCK_USER_PROJECTS_URL.replace("$", &context.id);
+
+        paginate(GITBLOCK_PAGE_SIZE, |page| {
+            paged_projects(context, &url, page)
+        })
+        .await
+    }
+}
+
+/// 用户作品与改编作品共用的分页接口，返回作品链接
+async fn paged_projects(context: &DownloadContext, url: &str, page: usize) -> Result<Vec<String>> {
+    let query = &[("pi", page + 1), ("ps", GITBLOCK_PAGE_SIZE)];
+    let response = context
+        .request(Method::POST, url)
+        .query(query)
+        .header(header::CONTENT_LENGTH, 0)
+        .send()
+        .await?;
+    let json = response.json::<GitblockPagedProjects>().await?;
+
+    Ok(json
+        .paged_projects
+        .items
+        .into_iter()
+        .map(|project| [GITBLOCK_PROJECT_PAGE_URL, &project.id.to_string()].concat())
+        .collect())
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::{
+    Author, Download, DownloadAssetServer, DownloadContext, DownloadDescriptor, ProjectMetadata,
+    StringOrNumber,
+};
+
+const PENGUINMOD_PROJECT_URL: &str = "https://projects.penguinmod.com/api/v1/projects/getproject";
+const PENGUINMOD_PAGE_URL: &str = "https://penguinmod.com/#";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PenguinModMetadata {
+    title: String,
+    author: Option<PenguinModAuthor>,
+    #[serde(alias = "instructions")]
+    notes: Option<String>,
+    views: Option<u64>,
+    loves: Option<u64>,
+    date: Option<StringOrNumber>,
+    last_update: Option<StringOrNumber>,
+    remix: Option<StringOrNumber>,
+}
+#[derive(Deserialize)]
+struct PenguinModAuthor {
+    id: Option<StringOrNumber>,
+    username: String,
+}
+
+/// PenguinMod 直接提供整个 .sb3 压缩包，资源从压缩包中读取
+pub struct PenguinModDownload;
+
+#[async_trait::async_trait]
+impl Download for PenguinModDownload {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "PenguinMod",
+            referer: "https://penguinmod.com/",
+            // 压缩包中缺少的资源多半来自 Scratch 素材库
+            asset_server: DownloadAssetServer::same("https://assets.scratch.mit.edu/"),
+        }
+    }
+    async fn get(&self, context: &mut DownloadContext) -> Result<()> {
+        let query = |request_type| {
+            [
+                ("projectID", context.id.clone()),
+                ("requestType", request_type),
+            ]
+        };
+
+        let res = context
+            .get(PENGUINMOD_PROJECT_URL)
+            .query(&query("metadata".into()))
+            .send()
+            .await?
+            .error_for_status()?;
+        let json = res.json::<PenguinModMetadata>().await?;
+
+        let sb3_url =
+            crate::utils::Url::parse_with_params(PENGUINMOD_PROJECT_URL, query("file".into()))?;
+        let thumbnail_url = crate::utils::Url::parse_with_params(
+            PENGUINMOD_PROJECT_URL,
+            query("thumbnail".into()),
+        )?;
+
+        let authors = json
+            .author
+            .map(|author| Author::new(author.username).with_id(author.id));
+        context.set_info(sb3_url, json.title, authors.into_iter().collect());
+        context.thumbnail = Some(thumbnail_url.into());
+        // 不是改编作品时为 0
+        context.parent = json
+            .remix
+            .filter(|id| id.0 != "0")
+            .map(|id| [PENGUINMOD_PAGE_URL, &String::from(id)].concat());
+        context.metadata = ProjectMetadata {
+            instructions: json.notes,
+            views: json.views,
+            likes: json.loves,
+            created: json.date.map(Into::into),
+            modified: json.last_update.map(Into::into),
+            ..Default::default()
+        };
+
+        Ok(())
+    }
+    fn decode(&self, _: &mut DownloadContext) -> Result<()> {
+        Ok(())
+    }
+}
@@ -1,53 +1,514 @@
-use anyhow::{anyhow, Result};
+use std::{fs, future::Future};
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
+use anyhow::{anyhow, Error, Result};
+use bytes::Bytes;
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
 
-const SCRATCH_API_PROXY_URL: &str = "https://trampoline.turbowarp.org/api/projects/";
-const SCRATCH_SB3_PROXY_URL: &str = "https://chilipar.alibga.icu/projects";
+use rabdog_schema::schema;
+
+use crate::utils::Url;
+
+use super::{
+    paginate, paginate_limited, Author, CloudVariables, Comment, Download, DownloadAssetServer,
+    DownloadContext, DownloadDescriptor, DownloadList, ProjectMetadata, CONTEXT, INVALID_PATH,
+};
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ScratchBackend {
+    /// 先直连官方接口，失败时改用代理
+    #[default]
+    Auto,
+    /// 直连 api.scratch.mit.edu、projects.scratch.mit.edu 与 assets.scratch.mit.edu
+    Direct,
+    /// 使用 TurboWarp Trampoline 与 Chilipar 代理
+    Proxy,
+}
+impl ScratchBackend {
+    fn endpoints(&self) -> &'static [ScratchEndpoints] {
+        match self {
+            ScratchBackend::Auto => &[SCRATCH_DIRECT, SCRATCH_PROXY],
+            ScratchBackend::Direct => &[SCRATCH_DIRECT],
+            ScratchBackend::Proxy => &[SCRATCH_PROXY],
+        }
+    }
+}
+
+/// 按 `--scratch-backend` 的顺序尝试各个接口，全部失败时返回最后一个错误
+async fn try_endpoints<T, F, Fut>(mut fetch: F) -> Result<T>
+where
+    F: FnMut(&'static ScratchEndpoints) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let (config, _, _) = CONTEXT.get().unwrap();
+
+    let mut last_error = None::<Error>;
+    for endpoints in config.scratch_backend.endpoints() {
+        match fetch(endpoints).await {
+            Ok(value) => return Ok(value),
+            Err(err) => last_error = Some(err),
+        }
+    }
+
+    Err(last_error.unwrap_or(anyhow!("no available scratch backend")))
+}
+
+/// 以 `limit`、`offset` 分页的接口
+async fn scratch_page<T: DeserializeOwned>(
+    context: &DownloadContext,
+    url: &str,
+    page: usize,
+) -> Result<Vec<T>> {
+    let offset = (page * SCRATCH_PAGE_SIZE).to_string();
+    let query = &[("limit", SCRATCH_PAGE_SIZE.to_string()), ("offset", offset)];
+
+    let res = context.get(url).query(query).send().await?;
+    Ok(res.error_for_status()?.json().await?)
+}
+
+struct ScratchEndpoints {
+    api: &'static str,
+    studios: &'static str,
+    users: &'static str,
+    projects: &'static str,
+    assets: &'static str,
+}
+const SCRATCH_DIRECT: ScratchEndpoints = ScratchEndpoints {
+    api: "https://api.scratch.mit.edu/projects/",
+    studios: "https://api.scratch.mit.edu/studios/",
+    users: "https://api.scratch.mit.edu/users/",
+    projects: "https://projects.scratch.mit.edu/",
+    assets: "https://assets.scratch.mit.edu/",
+};
+const SCRATCH_PROXY: ScratchEndpoints = ScratchEndpoints {
+    api: "https://trampoline.turbowarp.org/api/projects/",
+    studios: "https://trampoline.turbowarp.org/api/studios/",
+    users: "https://trampoline.turbowarp.org/api/users/",
+    projects: "https://chilipar.alibga.icu/projects/",
+    assets: "https://chilipar.alibga.icu/assets/",
+};
+
+const SCRATCH_PROJECT_PAGE_URL: &str = "https://scratch.mit.edu/projects/";
+const SCRATCH_PAGE_SIZE: usize = 40;
+const SCRATCH_CLOUD_LOGS_URL: &str = "https://clouddata.scratch.mit.edu/logs";
+const SCRATCH_CLOUD_PAGE_SIZE: usize = 100;
+/// 最多翻阅的云变量日志条数，更早之前修改过的云变量不再保存
+const SCRATCH_CLOUD_LOG_LIMIT: usize = 1000;
+
+schema! {
+    ScratchListedProject;
+    id: u64
+}
+
+#[derive(serde::Deserialize)]
+struct ScratchStudioResponse {
+    title: String,
+    #[serde(default)]
+    description: String,
+}
+#[derive(serde::Deserialize)]
+struct ScratchStudioProject {
+    id: u64,
+    #[serde(default)]
+    title: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ScratchCloudLog {
+    verb: String,
+    name: String,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+struct ScratchComment {
+    id: u64,
+    content: String,
+    datetime_created: Option<String>,
+    author: _ScratchResponseAuthor,
+    #[serde(default)]
+    reply_count: usize,
+}
+impl ScratchComment {
+    fn into_comment(self, replies: Vec<Comment>) -> Comment {
+        let author =
+            Author::new(self.author.username).with_id(self.author.id.map(|id| id.to_string()));
+
+        Comment {
+            id: self.id.to_string(),
+            author: Some(author),
+            content: self.content,
+            created: self.datetime_created,
+            replies,
+        }
+    }
+}
+
+/// 写入 `<工作室标题>.studio.json` 的清单，`projects` 保持工作室中的顺序
+#[derive(serde::Serialize)]
+struct ScratchStudioManifest {
+    id: String,
+    title: String,
+    description: String,
+    projects: Vec<ScratchStudioManifestProject>,
+}
+#[derive(serde::Serialize)]
+struct ScratchStudioManifestProject {
+    id: u64,
+    title: String,
+    url: String,
+}
 
 #[derive(serde::Deserialize)]
 pub struct ScratchResponse {
     pub title: String,
     pub project_token: String,
     pub author: _ScratchResponseAuthor,
+    #[serde(default)]
+    pub remix: _ScratchResponseRemix,
+    pub image: Option<String>,
+    pub description: Option<String>,
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub stats: _ScratchResponseStats,
+    #[serde(default)]
+    pub history: _ScratchResponseHistory,
 }
 #[derive(serde::Deserialize)]
 pub struct _ScratchResponseAuthor {
+    pub id: Option<u64>,
     pub username: String,
 }
+#[derive(serde::Deserialize, Default)]
+pub struct _ScratchResponseRemix {
+    pub parent: Option<u64>,
+}
+#[derive(serde::Deserialize, Default)]
+pub struct _ScratchResponseStats {
+    pub views: Option<u64>,
+    pub loves: Option<u64>,
+    pub favorites: Option<u64>,
+    pub remixes: Option<u64>,
+}
+#[derive(serde::Deserialize, Default)]
+pub struct _ScratchResponseHistory {
+    pub created: Option<String>,
+    pub modified: Option<String>,
+}
 
 pub struct ScratchDownload;
 
-#[async_trait::async_trait]
-impl Download for ScratchDownload {
-    fn descriptor(&self) -> DownloadDescriptor {
-        DownloadDescriptor {
-            display_name: "Scratch",
-            referer: "https://scratch.mit.edu/",
-            asset_server: DownloadAssetServer::same("https://chilipar.alibga.icu/assets/"),
-        }
+impl ScratchDownload {
+    fn backend(&self) -> ScratchBackend {
+        let (config, _, _) = CONTEXT.get().unwrap();
+        config.scratch_backend
     }
-    async fn get(&self, context: &mut DownloadContext) -> Result<()> {
-        let project_url = [SCRATCH_API_PROXY_URL, &context.id].concat();
 
-        let res = context.get(project_url).send().await?;
+    async fn get_from(
+        &self,
+        context: &DownloadContext,
+        endpoints: &ScratchEndpoints,
+    ) -> Result<(Url, ScratchResponse, Bytes)> {
+        let project_url = [endpoints.api, &context.id].concat();
+
+        let res = context.get(project_url).send().await?.error_for_status()?;
 
         let json = res.json::<ScratchResponse>().await?;
 
-        let mut sb3_url = crate::utils::Url::parse(SCRATCH_SB3_PROXY_URL)?;
+        let mut sb3_url = Url::parse(endpoints.projects)?;
         sb3_url
             .path_segments_mut()
             .map_err(|_| anyhow!("cannot be base"))?
+            .pop_if_empty()
             .push(&context.id);
         sb3_url
             .query_pairs_mut()
             .append_pair("token", &json.project_token);
 
-        context.set_info(sb3_url, json.title, vec![json.author.username]);
+        let res = context
+            .get(sb3_url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        let buffer = res.bytes().await?;
+
+        Ok((sb3_url, json, buffer))
+    }
+}
+
+impl ScratchDownload {
+    async fn comments_from(
+        &self,
+        context: &DownloadContext,
+        endpoints: &ScratchEndpoints,
+        limit: usize,
+    ) -> Result<Vec<Comment>> {
+        // 评论接口需要作者的用户名
+        let username = context
+            .authors
+            .first()
+            .map(|author| author.name.as_str())
+            .ok_or(anyhow!("unknown project author"))?;
+        let url = [
+            endpoints.users,
+            username,
+            "/projects/",
+            &context.id,
+            "/comments",
+        ]
+        .concat();
+
+        let threads = paginate_limited(SCRATCH_PAGE_SIZE, limit, |page| {
+            scratch_page::<ScratchComment>(context, &url, page)
+        })
+        .await?;
+
+        let mut comments = Vec::with_capacity(threads.len());
+        for thread in threads {
+            let replies = match thread.reply_count {
+                0 => Vec::new(),
+                _ => {
+                    let url = [&url, "/", &thread.id.to_string(), "/replies"].concat();
+                    paginate(SCRATCH_PAGE_SIZE, |page| {
+                        scratch_page::<ScratchComment>(context, &url, page)
+                    })
+                    .await?
+                    .into_iter()
+                    .map(|reply| reply.into_comment(Vec::new()))
+                    .collect()
+                }
+            };
+            comments.push(thread.into_comment(replies));
+        }
+
+        Ok(comments)
+    }
+    async fn remixes_from(
+        &self,
+        context: &DownloadContext,
+        endpoints: &ScratchEndpoints,
+    ) -> Result<Vec<String>> {
+        let url = [endpoints.api, &context.id, "/remixes"].concat();
+
+        let projects = paginate(SCRATCH_PAGE_SIZE, |page| {
+            scratch_page::<ScratchListedProject>(context, &url, page)
+        })
+        .await?;
+
+        Ok(projects
+            .into_iter()
+            .map(|project| [SCRATCH_PROJECT_PAGE_URL, &project.id.to_string()].concat())
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Download for ScratchDownload {
+    fn descriptor(&self) -> DownloadDescriptor {
+        // 按顺序尝试各个接口的资源服务器
+        let asset_server = self
+            .backend()
+            .endpoints()
+            .iter()
+            .rev()
+            .map(|endpoints| DownloadAssetServer::same(endpoints.assets))
+            .reduce(|fallback, server| server.or(fallback))
+            .unwrap_or_default();
+
+        DownloadDescriptor {
+            display_name: "Scratch",
+            referer: "https://scratch.mit.edu/",
+            asset_server,
+        }
+    }
+    async fn get(&self, context: &mut DownloadContext) -> Result<()> {
+        let (sb3_url, json, buffer) =
+            try_endpoints(|endpoints| self.get_from(context, endpoints)).await?;
+
+        let author =
+            Author::new(json.author.username).with_id(json.author.id.map(|id| id.to_string()));
+        context.set_info(sb3_url, json.title, vec![author]);
+        context.set_buffer(buffer);
+        context.thumbnail = json.image;
+        context.metadata = ProjectMetadata {
+            description: json.description,
+            instructions: json.instructions,
+            views: json.stats.views,
+            likes: json.stats.loves,
+            favorites: json.stats.favorites,
+            remixes: json.stats.remixes,
+            created: json.history.created,
+            modified: json.history.modified,
+            ..Default::default()
+        };
+        context.parent = json
+            .remix
+            .parent
+            .map(|id| [SCRATCH_PROJECT_PAGE_URL, &id.to_string()].concat());
 
         Ok(())
     }
+    async fn comments(
+        &self,
+        context: &mut DownloadContext,
+        limit: usize,
+    ) -> Result<Option<Vec<Comment>>> {
+        let context = &*context;
+        let comments =
+            try_endpoints(|endpoints| self.comments_from(context, endpoints, limit)).await?;
+
+        Ok(Some(comments))
+    }
+    async fn cloud_variables(&self, context: &mut DownloadContext) -> Result<CloudVariables> {
+        let context = &*context;
+
+        let logs = paginate_limited(
+            SCRATCH_CLOUD_PAGE_SIZE,
+            SCRATCH_CLOUD_LOG_LIMIT,
+            |page| async move {
+                let query = &[
+                    ("projectid", context.id.clone()),
+                    ("limit", SCRATCH_CLOUD_PAGE_SIZE.to_string()),
+                    ("offset", (page * SCRATCH_CLOUD_PAGE_SIZE).to_string()),
+                ];
+                let res = context
+                    .get(SCRATCH_CLOUD_LOGS_URL)
+                    .query(query)
+                    .send()
+                    .await?;
+
+                Ok(res
+                    .error_for_status()?
+                    .json::<Vec<ScratchCloudLog>>()
+                    .await?)
+            },
+        )
+        .await?;
+
+        // 日志从新到旧排列，只取每个云变量最近一次的值
+        let mut values = CloudVariables::new();
+        for log in logs {
+            if values.contains_key(&log.name) {
+                continue;
+            }
+            match log.verb.as_str() {
+                "set_var" | "create_var" => values.insert(log.name, log.value),
+                "del_var" => values.insert(log.name, serde_json::Value::Null),
+                _ => None,
+            };
+        }
+        values.retain(|_, value| !value.is_null());
+
+        Ok(values)
+    }
+    async fn remixes(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+        try_endpoints(|endpoints| self.remixes_from(context, endpoints)).await
+    }
     fn decode(&self, _: &mut DownloadContext) -> Result<()> {
         Ok(())
     }
 }
+
+pub struct ScratchUserList;
+
+impl ScratchUserList {
+    async fn list_from(
+        &self,
+        context: &DownloadContext,
+        endpoints: &ScratchEndpoints,
+    ) -> Result<Vec<String>> {
+        let url = [endpoints.users, &context.id, "/projects"].concat();
+
+        let projects = paginate(SCRATCH_PAGE_SIZE, |page| {
+            scratch_page::<ScratchListedProject>(context, &url, page)
+        })
+        .await?;
+
+        Ok(projects
+            .into_iter()
+            .map(|project| [SCRATCH_PROJECT_PAGE_URL, &project.id.to_string()].concat())
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl DownloadList for ScratchUserList {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "Scratch 用户",
+            referer: "https://scratch.mit.edu/",
+            ..Default::default()
+        }
+    }
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+        try_endpoints(|endpoints| self.list_from(context, endpoints)).await
+    }
+}
+
+pub struct ScratchStudioList;
+
+impl ScratchStudioList {
+    async fn list_from(
+        &self,
+        context: &DownloadContext,
+        endpoints: &ScratchEndpoints,
+    ) -> Result<ScratchStudioManifest> {
+        let studio_url = [endpoints.studios, &context.id].concat();
+        let res = context.get(&studio_url).send().await?.error_for_status()?;
+        let studio = res.json::<ScratchStudioResponse>().await?;
+
+        let projects_url = [&studio_url, "/projects"].concat();
+        let projects = paginate(SCRATCH_PAGE_SIZE, |page| {
+            scratch_page::<ScratchStudioProject>(context, &projects_url, page)
+        })
+        .await?;
+
+        Ok(ScratchStudioManifest {
+            id: context.id.clone(),
+            title: studio.title,
+            description: studio.description,
+            projects: projects
+                .into_iter()
+                .map(|project| ScratchStudioManifestProject {
+                    id: project.id,
+                    title: project.title,
+                    url: [SCRATCH_PROJECT_PAGE_URL, &project.id.to_string()].concat(),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl DownloadList for ScratchStudioList {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "Scratch 工作室",
+            referer: "https://scratch.mit.edu/",
+            ..Default::default()
+        }
+    }
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let (config, _, _) = CONTEXT.get().unwrap();
+
+        let context = &*context;
+        let manifest = try_endpoints(|endpoints| self.list_from(context, endpoints)).await?;
+
+        if config.studio_manifest {
+            let mut title = manifest.title.clone();
+            title.retain(|c| !INVALID_PATH.contains(c));
+
+            let path = config.path.join([&title, ".studio.json"].concat());
+            fs::write(path, serde_json::to_vec_pretty(&manifest)?)?;
+        }
+
+        Ok(manifest
+            .projects
+            .into_iter()
+            .map(|project| project.url)
+            .collect())
+    }
+}
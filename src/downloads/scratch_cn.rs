@@ -1,15 +1,16 @@
 use anyhow::Result;
 use scraper::Html;
 
-use crate::selector;
+use crate::{selector, utils::get_og_image};
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
+use super::{Author, Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
 
 const SCRATCHCN_SB3_URL: &str = "https://www.xiaoyaqian.cn/userfile/scratch/";
 const SCRATCHCN_PROJECT_URL: &str = "https://www.scratch-cn.cn/project/?comid=";
 
 selector!(PROJECT_ID_SELECTOR, "#_s_");
 selector!(PROJECT_TITLE_SELECTOR, ".work-title > h3");
+selector!(PROJECT_AUTHOR_SELECTOR, ".work-author a");
 
 pub struct ScratchCNDownload;
 
@@ -26,7 +27,7 @@ impl Download for ScratchCNDownload {
     }
     async fn get(&self, context: &mut DownloadContext) -> Result<()> {
         let project_url = [SCRATCHCN_PROJECT_URL, &context.id].concat();
-        let res = context.get(project_url).send().await?.text().await?;
+        let res = context.get(&project_url).send().await?.text().await?;
 
         let document = Html::parse_document(&res);
         let project_id = document
@@ -43,8 +44,21 @@ impl Download for ScratchCNDownload {
             .next()
             .unwrap();
 
+        // 作者链接形如 /user/?uid=<用户 ID>
+        let authors = document
+            .select(&PROJECT_AUTHOR_SELECTOR)
+            .next()
+            .map(|author| {
+                let id = author
+                    .attr("href")
+                    .and_then(|href| href.split_once("uid="))
+                    .map(|(_, id)| id.split('&').next().unwrap_or(id));
+                Author::new(author.text().collect::<String>().trim()).with_id(id)
+            });
+
         let sb3_url = [SCRATCHCN_SB3_URL, project_id].concat();
-        context.set_info(sb3_url, title.to_owned(), vec![]);
+        context.set_info(sb3_url, title.to_owned(), authors.into_iter().collect());
+        context.thumbnail = get_og_image(&res, &project_url);
 
         Ok(())
     }
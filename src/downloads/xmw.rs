@@ -8,11 +8,17 @@ use crate::utils::{
     get_next_data,
 };
 
-use super::{Download, DownloadAssetServer, DownloadContext, DownloadDescriptor};
+use super::{
+    paginate, Author, Download, DownloadAssetServer, DownloadContext, DownloadDescriptor,
+    DownloadList, StringOrNumber,
+};
 
 const XMW_PROJECT_URL: &str = "https://world.xiaomawang.com/community/main/compose/";
 const XMW_SB3_URL: &str =
     "https://community-api.xiaomawang.com/japi/v1/composition/get-encrypt-sb3";
+const XMW_USER_PROJECTS_URL: &str =
+    "https://community-api.xiaomawang.com/japi/v1/composition/user-compositions";
+const XMW_PAGE_SIZE: usize = 20;
 const XMW_AES_KEY: &str = "xmwcommunityskey";
 const XMW_AES_IV: &str = "0392139263920300";
 const XMW_PROJECT_KEY_PREFIX: &str = "xiaomw135";
@@ -24,7 +30,16 @@ schema! {
 
 schema! {
     XMWData;
-    props.initial_state.detail.compose_info.title: String
+    props.initial_state.detail.compose_info.title: String,
+    props.initial_state.detail.compose_info.cover: Option<String>,
+    props.initial_state.detail.compose_info.description: Option<String>,
+    props.initial_state.detail.compose_info.nickname: Option<String>,
+    props.initial_state.detail.compose_info.user_id: Option<StringOrNumber>
+}
+
+schema! {
+    XMWUserCompositions;
+    data.list[].composition_encrypt_id: String
 }
 
 #[derive(Default)]
@@ -39,6 +54,7 @@ impl Download for XMWDownload {
             asset_server: DownloadAssetServer::split(
                 "https://community-wscdn.xiaomawang.com/picture/",
                 "https://community-wscdn.xiaomawang.com/audio/",
+                "https://community-wscdn.xiaomawang.com/picture/",
             ),
         }
     }
@@ -56,7 +72,17 @@ impl Download for XMWDownload {
             XMW_SB3_URL,
             &[("compositionEncryptId", context.id.clone())],
         )?;
-        context.set_info(project_url.clone(), json.compose_info.title, Vec::new());
+        let info = json.compose_info;
+        let authors = info
+            .nickname
+            .map(|nickname| Author::new(nickname).with_id(info.user_id));
+        context.set_info(
+            project_url.clone(),
+            info.title,
+            authors.into_iter().collect(),
+        );
+        context.thumbnail = info.cover;
+        context.metadata.description = info.description;
 
         let res = context.get(project_url).send().await?;
         let data = res.json::<XMWProjectEncodedSb3>().await?.data;
@@ -89,3 +115,43 @@ impl Download for XMWDownload {
         Ok(())
     }
 }
+
+#[derive(Default)]
+pub struct XMWUserList;
+
+#[async_trait::async_trait]
+impl DownloadList for XMWUserList {
+    fn descriptor(&self) -> DownloadDescriptor {
+        DownloadDescriptor {
+            display_name: "小码王用户",
+            referer: "https://world.xiaomawang.com/",
+            ..Default::default()
+        }
+    }
+
+    async fn list(&self, context: &mut DownloadContext) -> Result<Vec<String>> {
+        let context = &*context;
+
+        paginate(XMW_PAGE_SIZE, |page| async move {
+            let query = &[
+                ("userId", context.id.clone()),
+                ("page", (page + 1).to_string()),
+                ("pageSize", XMW_PAGE_SIZE.to_string()),
+            ];
+            let res = context
+                .get(XMW_USER_PROJECTS_URL)
+                .query(query)
+                .send()
+                .await?;
+            let json = res.json::<XMWUserCompositions>().await?;
+
+            Ok(json
+                .data
+                .list
+                .into_iter()
+                .map(|composition| [XMW_PROJECT_URL, &composition.composition_encrypt_id].concat())
+                .collect())
+        })
+        .await
+    }
+}
@@ -1,12 +1,25 @@
 use std::path::PathBuf;
 
-use crate::downloads::setup_static;
 use crate::downloads::{
-    ccw::CCWDownload, clipcc::ClipccDownload, cocrea::CocreaDownload, fortycode::FortycodeDownload,
-    gitblock::GitblockDownload, scratch::ScratchDownload, scratch_cn::ScratchCNDownload,
-    xmw::XMWDownload,
+    ccw::{CCWDownload, CCWUserList},
+    clipcc::{ClipccDownload, ClipccUserList},
+    cocrea::CocreaDownload,
+    direct::DirectDownload,
+    fortycode::FortycodeDownload,
+    gitblock::{GitblockDownload, GitblockUserList},
+    penguinmod::PenguinModDownload,
+    scratch::{ScratchBackend, ScratchDownload, ScratchStudioList, ScratchUserList},
+    scratch_cn::ScratchCNDownload,
+    xmw::{XMWDownload, XMWUserList},
 };
+use crate::downloads::{setup_static, CloudSnapshot, DownloadVersions};
 use crate::output::output_channel;
+use crate::utils::{
+    extensions::{ExtensionBundle, TargetPlayer},
+    lineage::LineageFormat,
+    sb3::compat::CompatTransform,
+    Url,
+};
 
 use clap::{value_parser, Parser};
 use futures::future::join_all;
@@ -18,11 +31,24 @@ mod output;
 mod utils;
 
 macro_rules! downloads {
-    ($manager:ident; $($init:expr => $matcher:literal),*) => {
+    (
+        $manager:ident;
+        $($name:literal: $init:expr => $matcher:literal),*;
+        fallback $fallback:expr => $fallback_matcher:literal;
+        $($list:expr => $list_matcher:literal),*;
+        $($alternate:literal => $canonical:literal),*
+    ) => {
         static $manager: once_cell::sync::Lazy<$crate::downloads::DownloadManager> = once_cell::sync::Lazy::new(|| {
             let mut manager = $crate::downloads::DownloadManager::new();
             $(
-                manager.add($matcher, || Box::new($init));
+                manager.add($name, $matcher, || Box::new($init));
+            )*
+            manager.set_fallback($fallback_matcher, || Box::new($fallback));
+            $(
+                manager.add_list($list_matcher, || Box::new($list));
+            )*
+            $(
+                manager.add_canonical($alternate, $canonical);
             )*
             manager
         });
@@ -30,20 +56,43 @@ macro_rules! downloads {
 }
 
 downloads!(MANAGER;
-    CCWDownload => r"^((https|http):\/\/)?(www\.)?ccw\.site\/detail\/(?<id>[a-z0-9]{24})(\?.*)?",
-    ClipccDownload => r"^((https|http):\/\/)?codingclip\.com\/project\/(?<id>[0-9]+)(\?.*)?",
-    CocreaDownload => r"((https|http):\/\/)?(www\.)?cocrea\.world\/(?<id>\@(\w+)\/(\w+))(\?.*)?",
-    XMWDownload => r"^((https|http):\/\/)?world.xiaomawang.com\/community\/main\/compose\/(?<id>[a-zA-Z0-9]{8})(\?.*)?",
-    ScratchCNDownload => r"^((https|http):\/\/)?(www\.)?scratch-cn.cn\/project\/\?comid=(?<id>[a-zA-Z0-9]{24})(\?.*)?",
-    FortycodeDownload => r"^((https|http):\/\/)?(www\.)?40code.com\/#page=work&id=(?<id>[0-9]+)(\?.*)?",
-    GitblockDownload => r"^((https|http):\/\/)?(gitblock.cn|aerfaying.com)\/Projects/(?<id>[0-9]+)(\?.*)?",
-    ScratchDownload => r"^((https|http):\/\/)?scratch.mit.edu\/projects\/(?<id>[0-9]+)(\?.*)?"
+    "共创世界": CCWDownload => r"^((https|http):\/\/)?(www\.)?ccw\.site\/detail\/(?<id>[a-z0-9]{24})(\?.*)?",
+    "Clipcc": ClipccDownload => r"^((https|http):\/\/)?codingclip\.com\/project\/(?<id>[0-9]+)(\?.*)?",
+    "Cocrea World": CocreaDownload => r"((https|http):\/\/)?(www\.)?cocrea\.world\/(?<id>\@(\w+)\/(\w+))(\?.*)?",
+    "小码王": XMWDownload => r"^((https|http):\/\/)?world.xiaomawang.com\/community\/main\/compose\/(?<id>[a-zA-Z0-9]{8})(\?.*)?",
+    "Scratch中社": ScratchCNDownload => r"^((https|http):\/\/)?(www\.)?scratch-cn.cn\/project\/\?comid=(?<id>[a-zA-Z0-9]{24})(\?.*)?",
+    "40code": FortycodeDownload => r"^((https|http):\/\/)?(www\.)?40code.com\/#page=work&id=(?<id>[0-9]+)(\?.*)?",
+    "稽木世界": GitblockDownload => r"^((https|http):\/\/)?(gitblock.cn|aerfaying.com)\/Projects/(?<id>[0-9]+)(\?.*)?",
+    "PenguinMod": PenguinModDownload => r"^((https|http):\/\/)?(studio\.)?penguinmod\.com\/?(\?.*)?#(?<id>[0-9]+)",
+    "Scratch": ScratchDownload => r"^((https|http):\/\/)?scratch.mit.edu\/projects\/(?<id>[0-9]+)(\?.*)?";
+
+    // 其它社区都不匹配时才会使用
+    fallback DirectDownload => r"^(?<id>(https|http):\/\/[^#?]+\.(sb3|sb2|json)(\?[^#]*)?)$";
+
+    CCWUserList => r"^((https|http):\/\/)?(www\.)?ccw\.site\/student\/(?<id>[a-z0-9]{24})(\?.*)?",
+    ClipccUserList => r"^((https|http):\/\/)?codingclip\.com\/user\/(?<id>[0-9]+)(\?.*)?",
+    XMWUserList => r"^((https|http):\/\/)?world.xiaomawang.com\/community\/main\/person\/(?<id>[0-9]+)(\?.*)?",
+    GitblockUserList => r"^((https|http):\/\/)?(gitblock.cn|aerfaying.com)\/Users/(?<id>[0-9]+)(\?.*)?",
+    ScratchStudioList => r"^((https|http):\/\/)?scratch.mit.edu\/studios\/(?<id>[0-9]+)\/?(\?.*)?",
+    ScratchUserList => r"^((https|http):\/\/)?scratch.mit.edu\/users\/(?<id>[\w-]+)\/?(\?.*)?";
+
+    // 手机版、编辑器、嵌入页与 TurboWarp 链接，以及附带的跟踪参数
+    r"(?i)^(https?:\/\/)?((www|m)\.)?ccw\.site\/detail\/(?<id>[a-z0-9]{24})([\/?#].*)?$" => "https://www.ccw.site/detail/${id}",
+    r"^(https?:\/\/)?(www\.)?codingclip\.com\/project\/(?<id>[0-9]+)([\/?#].*)?$" => "https://codingclip.com/project/${id}",
+    r"^(https?:\/\/)?world\.xiaomawang\.com\/community\/main\/compose\/(?<id>[a-zA-Z0-9]{8})([\/?#].*)?$" => "https://world.xiaomawang.com/community/main/compose/${id}",
+    r"(?i)^(https?:\/\/)?(www\.)?(gitblock\.cn|aerfaying\.com)\/projects\/(?<id>[0-9]+)([\/?#].*)?$" => "https://gitblock.cn/Projects/${id}",
+    r"(?i)^(https?:\/\/)?(www\.)?(gitblock\.cn|aerfaying\.com)\/users\/(?<id>[0-9]+)([\/?#].*)?$" => "https://gitblock.cn/Users/${id}",
+    r"^(https?:\/\/)?(www\.|studio\.)?penguinmod\.com\/?(\?[^#]*)?#(?<id>[0-9]+).*$" => "https://penguinmod.com/#${id}",
+    r"^(https?:\/\/)?(www\.)?scratch\.mit\.edu\/projects\/(?<id>[0-9]+)([\/?#].*)?$" => "https://scratch.mit.edu/projects/${id}",
+    r"^(https?:\/\/)?(www\.)?scratch\.mit\.edu\/studios\/(?<id>[0-9]+)([\/?#].*)?$" => "https://scratch.mit.edu/studios/${id}",
+    r"^(https?:\/\/)?(www\.)?turbowarp\.org\/(?<id>[0-9]+)([\/?#].*)?$" => "https://scratch.mit.edu/projects/${id}",
+    r"^(https?:\/\/)?(www\.)?turbowarp\.org\/(editor|embed|fullscreen)?(\.html)?(\?[^#]*)?#(?<id>[0-9]+).*$" => "https://scratch.mit.edu/projects/${id}"
 );
 
 #[derive(Parser, Clone)]
 #[command(arg_required_else_help(true), version, about, long_about = None)]
 pub struct Config {
-    /// 社区作品链接
+    /// 社区作品或用户主页链接
     #[arg(required(true), value_parser = is_source_valid)]
     sources: Vec<String>,
 
@@ -56,14 +105,91 @@ pub struct Config {
     /// 是否不在终端输出下载进度
     #[arg(short, long)]
     silent: bool,
+    /// 是否生成可复现的 .sb3 文件 (固定条目顺序、修改时间与压缩参数)
+    #[arg(short, long)]
+    deterministic: bool,
+    /// 是否以键排序的规范形式写入 project.json
+    #[arg(long)]
+    canonical_json: bool,
+    /// .sb3 文件的 deflate 压缩等级 (0 为不压缩，1-9，10-264 使用 Zopfli 进行归档级压缩)
+    #[arg(short, long, value_parser = value_parser!(i64).range(0..=264))]
+    compression_level: Option<i64>,
+    /// 是否不再压缩 png、mp3 等已压缩的资源
+    #[arg(long)]
+    store_media: bool,
+    /// 下载 project.json 中写明地址的插件源码并保存到指定位置
+    #[arg(long, value_enum)]
+    bundle_extensions: Option<ExtensionBundle>,
+    /// 是否将 project.json 中的插件地址改写为 data: 链接，使作品可以离线加载
+    #[arg(long)]
+    inline_extensions: bool,
+    /// 检查插件兼容性时所针对的播放器
+    #[arg(short, long, value_enum, default_value_t)]
+    target_player: TargetPlayer,
+    /// 移除目标播放器不支持的插件积木
+    #[arg(long, value_enum)]
+    compat: Option<CompatTransform>,
+    /// 下载 Scratch 作品时使用的接口
+    #[arg(long, value_enum, default_value_t)]
+    scratch_backend: ScratchBackend,
+    /// 下载 Scratch 工作室时写入记录标题、简介与作品顺序的清单文件
+    #[arg(long)]
+    studio_manifest: bool,
+    /// 下载作品的历史版本 (all、单个版本 N 或范围 A-B)，目前仅支持稽木世界
+    #[arg(long, value_name = "VERSIONS")]
+    history: Option<DownloadVersions>,
+    /// 同时下载作品改编自的原作品，直到最初的作品 (支持 Scratch、共创世界、稽木世界与 PenguinMod)
+    #[arg(long, conflicts_with = "history")]
+    with_parents: bool,
+    /// 同时下载改编自该作品的作品，可指定向下的层数 (默认为 1，支持 Scratch、共创世界与稽木世界)
+    #[arg(
+        long,
+        conflicts_with = "history",
+        value_name = "DEPTH",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1"
+    )]
+    with_remixes: Option<usize>,
+    /// 改编族谱的格式
+    #[arg(long, value_enum, default_value_t)]
+    lineage_format: LineageFormat,
+    /// 是否把作品封面保存在 .sb3 文件旁
+    #[arg(long)]
+    thumbnail: bool,
+    /// 是否把简介、统计数据等作品信息写入 .sb3 文件旁的 .meta.json
+    #[arg(long)]
+    metadata: bool,
+    /// 是否把作品的评论及回复写入 .sb3 文件旁的 .comments.json (支持 Scratch、共创世界与 Gitblock)
+    #[arg(long)]
+    comments: bool,
+    /// 最多保存的评论数 (不含回复)
+    #[arg(long, requires = "comments")]
+    max_comments: Option<usize>,
+    /// 保存云变量当前的值
+    #[arg(long, value_enum)]
+    cloud: Option<CloudSnapshot>,
+    /// 直接下载 project.json 链接时的资源地址前缀，默认为 Scratch 资源服务器
+    #[arg(long, value_name = "URL", value_parser = parse_asset_base)]
+    asset_base: Option<String>,
 }
 
 fn is_source_valid(source: &str) -> Result<String, String> {
     let m = Lazy::force(&MANAGER);
 
-    match m.is_valid(source) {
-        true => Ok(source.into()),
-        false => Err("没有能胜任此链接的下载器".into()),
+    m.check(source).map_err(|err| err.to_string())
+}
+
+/// 资源文件名直接拼接在前缀后，缺少结尾的 `/` 时补上
+fn parse_asset_base(base: &str) -> Result<String, String> {
+    let url = Url::parse(base).map_err(|err| err.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("资源地址需要是 http(s) 链接".to_string());
+    }
+
+    match base.ends_with('/') {
+        true => Ok(base.to_string()),
+        false => Ok([base, "/"].concat()),
     }
 }
 
@@ -74,11 +200,10 @@ fn main() -> anyhow::Result<()> {
     setup_static(config.clone(), tx.clone());
 
     let manager = Lazy::force(&MANAGER);
-    let sources = config.sources.as_slice();
-    let tasks = sources.iter().enumerate().filter_map(|(idx, source)| {
-        let mut download = manager.select(source)?;
-        Some(async move { download.download(idx).await })
-    });
+    let tasks = config
+        .sources
+        .iter()
+        .map(|source| manager.dispatch(source.clone()));
 
     let rt = Runtime::new()?;
     rt.block_on(async move {
@@ -94,3 +219,99 @@ fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloads::DownloadManager;
+
+    #[test]
+    fn canonical_sources() {
+        let cases = [
+            (
+                "https://m.ccw.site/detail/65b9182433db685782f24f8f?inviteCode=abc",
+                "https://www.ccw.site/detail/65b9182433db685782f24f8f",
+            ),
+            (
+                "ccw.site/detail/65b9182433db685782f24f8f#comments",
+                "https://www.ccw.site/detail/65b9182433db685782f24f8f",
+            ),
+            (
+                "https://scratch.mit.edu/projects/10128407/editor",
+                "https://scratch.mit.edu/projects/10128407",
+            ),
+            (
+                "https://scratch.mit.edu/projects/10128407/embed",
+                "https://scratch.mit.edu/projects/10128407",
+            ),
+            (
+                "https://scratch.mit.edu/projects/10128407/#fullscreen",
+                "https://scratch.mit.edu/projects/10128407",
+            ),
+            (
+                "https://turbowarp.org/editor#10128407",
+                "https://scratch.mit.edu/projects/10128407",
+            ),
+            (
+                "https://turbowarp.org/10128407/fullscreen?fps=60",
+                "https://scratch.mit.edu/projects/10128407",
+            ),
+            (
+                "https://www.aerfaying.com/Projects/114514",
+                "https://gitblock.cn/Projects/114514",
+            ),
+            (
+                "https://studio.penguinmod.com/#1919810",
+                "https://penguinmod.com/#1919810",
+            ),
+            (
+                " https://codingclip.com/project/114/ ",
+                "https://codingclip.com/project/114",
+            ),
+            (
+                "https://scratch.mit.edu/studios/5000000/projects",
+                "https://scratch.mit.edu/studios/5000000",
+            ),
+        ];
+
+        for (source, canonical) in cases {
+            assert_eq!(MANAGER.canonicalize(source), canonical, "{}", source);
+            assert!(MANAGER.check(source).is_ok(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn unknown_sources() {
+        assert_eq!(
+            MANAGER.canonicalize("https://example.com/a?b#c"),
+            "https://example.com/a?b#c"
+        );
+        assert!(MANAGER.check("ftp://example.com/a").is_err());
+    }
+
+    #[test]
+    fn ambiguous_sources() {
+        let mut manager = DownloadManager::new();
+        manager.add("Scratch", r"^(?<id>[0-9]+)$", || Box::new(ScratchDownload));
+        manager.add("PenguinMod", r"^(?<id>[0-9]+)$", || {
+            Box::new(PenguinModDownload)
+        });
+
+        let err = manager.check("1").unwrap_err().to_string();
+        assert!(err.contains("Scratch, PenguinMod"), "{}", err);
+    }
+
+    #[test]
+    fn asset_base_ends_with_slash() {
+        assert_eq!(
+            parse_asset_base("https://example.com/assets").unwrap(),
+            "https://example.com/assets/"
+        );
+        assert_eq!(
+            parse_asset_base("https://example.com/assets/").unwrap(),
+            "https://example.com/assets/"
+        );
+        assert!(parse_asset_base("assets").is_err());
+        assert!(parse_asset_base("file:///assets").is_err());
+    }
+}
@@ -1,8 +1,9 @@
+use crate::utils::{extensions::CompatibilityReport, sb3::compat::CompatTransformReport};
 use anyhow::{Error, Result};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use once_cell::sync::Lazy;
 use owo_colors::{AnsiColors, OwoColorize};
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 static SPINNER_STYLE: Lazy<ProgressStyle> = Lazy::new(|| {
@@ -18,9 +19,16 @@ pub enum NotificationIndex {
 pub enum Notification {
     SelectedDownload { name: &'static str, id: String },
     FetchedProject(String),
+    ListedProjects(usize),
     DecodedProject,
     DownloadedAsset(String),
-    WarnCommunityExtensions(Vec<String>),
+    BundledExtension(String),
+    SavedThumbnail,
+    SavedComments(usize),
+    SavedCloudVariables(usize),
+    Warn(String),
+    WarnIncompatibleExtensions(CompatibilityReport),
+    TransformedProject(CompatTransformReport),
     Finished,
     Canceled,
     Error(Error),
@@ -30,14 +38,19 @@ impl Display for Notification {
         match self {
             Notification::SelectedDownload { .. } => write!(f, "..."),
             Notification::FetchedProject(title) => write!(f, "获取到作品信息 标题: {}", title),
+            Notification::ListedProjects(count) => write!(f, "找到 {} 个作品", count),
             Notification::DecodedProject => write!(f, "作品解码完成"),
             Notification::DownloadedAsset(asset) => write!(f, "摆好资源: {}", asset),
+            Notification::BundledExtension(id) => write!(f, "保存插件: {}", id),
+            Notification::SavedThumbnail => write!(f, "保存封面"),
+            Notification::SavedComments(count) => write!(f, "保存 {} 条评论", count),
+            Notification::SavedCloudVariables(count) => write!(f, "保存 {} 个云变量", count),
             Notification::Finished => write!(f, "下载完成"),
             Notification::Canceled => write!(f, "下载作品任务已取消"),
             Notification::Error(err) => write!(f, "遇到错误: {}", err),
-            Notification::WarnCommunityExtensions(items) => {
-                write!(f, "⚠️ 下载项目中有不兼容的社区插件: {}", items.join(", "))
-            }
+            Notification::Warn(warning) => write!(f, "{}", warning),
+            Notification::WarnIncompatibleExtensions(report) => write!(f, "{}", report),
+            Notification::TransformedProject(report) => write!(f, "{}", report),
         }
     }
 }
@@ -102,14 +115,14 @@ impl NotifyProgress {
 pub struct OutputReceiver {
     inner: UnboundedReceiver<OutputMessage>,
     multi: MultiProgress,
-    bars: Vec<NotifyProgress>,
+    bars: BTreeMap<usize, NotifyProgress>,
 }
 impl OutputReceiver {
     pub fn empty(inner: UnboundedReceiver<OutputMessage>) -> Self {
         Self {
             inner,
             multi: MultiProgress::new(),
-            bars: Vec::new(),
+            bars: BTreeMap::new(),
         }
     }
 
@@ -119,11 +132,13 @@ impl OutputReceiver {
         }
     }
     pub fn do_actions(&mut self, index: NotificationIndex, notification: Notification) {
-        let range = match index {
-            NotificationIndex::All => 0..self.bars.len(),
-            NotificationIndex::Single(idx) => idx..idx + 1,
+        let indexes = match index {
+            NotificationIndex::All => self.bars.keys().copied().collect(),
+            NotificationIndex::Single(idx) => vec![idx],
         };
-        range.for_each(|idx| self.act(idx, &notification))
+        indexes
+            .into_iter()
+            .for_each(|idx| self.act(idx, &notification))
     }
 
     fn act(&mut self, idx: usize, notification: &Notification) {
@@ -132,10 +147,12 @@ impl OutputReceiver {
             let mut bar = NotifyProgress::new(description);
             bar.remote(&mut self.multi);
 
-            self.bars.push(bar);
+            self.bars.insert(idx, bar);
         }
 
-        let bar = &mut self.bars[idx];
+        let Some(bar) = self.bars.get_mut(&idx) else {
+            return;
+        };
         if let ProgressStatus::Running = bar.status {
             let status = match notification {
                 Notification::Finished => ProgressStatus::Finished,
@@ -147,7 +164,9 @@ impl OutputReceiver {
             bar.set_status(status);
 
             match notification {
-                Notification::WarnCommunityExtensions(_) => {
+                Notification::Warn(_)
+                | Notification::WarnIncompatibleExtensions(_)
+                | Notification::TransformedProject(_) => {
                     bar.println(notification.yellow().to_string())
                 }
                 _ => bar.update(notification),
@@ -3,6 +3,9 @@ use scraper::Html;
 
 pub use reqwest::Url;
 pub mod decode;
+pub mod extensions;
+pub mod lineage;
+pub mod sb2;
 pub mod sb3;
 
 #[macro_export]
@@ -25,3 +28,95 @@ pub fn get_next_data(text: &str) -> Result<String> {
 
     Ok(text)
 }
+
+selector!(OG_IMAGE_SELECTOR, r#"meta[property="og:image"]"#);
+
+/// 页面分享卡片使用的封面，相对地址按 `base` 补全
+pub fn get_og_image(text: &str, base: &str) -> Option<String> {
+    let document = Html::parse_document(text);
+    let image = document
+        .select(&OG_IMAGE_SELECTOR)
+        .next()?
+        .attr("content")?;
+
+    Url::parse(base)
+        .ok()?
+        .join(image.trim())
+        .ok()
+        .map(Into::into)
+}
+
+selector!(META_REFRESH_SELECTOR, "meta[http-equiv][content]");
+static JS_LOCATION_REGEX: once_cell::sync::Lazy<regex::Regex> = once_cell::sync::Lazy::new(|| {
+    regex::Regex::new(
+        r#"location(\.href)?\s*=\s*["'](?<url>[^"']+)["']|location\.(replace|assign)\(\s*["'](?<call>[^"']+)["']"#,
+    )
+    .unwrap()
+});
+
+/// 找出页面中 `<meta http-equiv="refresh">` 或脚本修改 `location` 指向的地址
+pub fn find_html_redirect(text: &str) -> Option<String> {
+    let document = Html::parse_document(text);
+    let refresh = document
+        .select(&META_REFRESH_SELECTOR)
+        .filter(|e| {
+            e.attr("http-equiv")
+                .unwrap()
+                .eq_ignore_ascii_case("refresh")
+        })
+        .find_map(|e| {
+            let (_, url) = e.attr("content").unwrap().split_once(';')?;
+            let (key, url) = url.split_once('=')?;
+            key.trim()
+                .eq_ignore_ascii_case("url")
+                .then(|| url.trim().trim_matches(['"', '\'']).to_owned())
+        });
+
+    refresh.or_else(|| {
+        let caps = JS_LOCATION_REGEX.captures(text)?;
+        let url = caps.name("url").or(caps.name("call"))?;
+
+        Some(url.as_str().to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_refresh() {
+        let html = r#"<meta http-equiv="Refresh" content="0; url=https://example.com/a">"#;
+        assert_eq!(
+            find_html_redirect(html).as_deref(),
+            Some("https://example.com/a")
+        );
+
+        let html = r#"<meta http-equiv="refresh" content="3;URL='https://example.com/b'">"#;
+        assert_eq!(
+            find_html_redirect(html).as_deref(),
+            Some("https://example.com/b")
+        );
+    }
+
+    #[test]
+    fn script_location() {
+        let html = r#"<script>window.location.href = "https://example.com/c";</script>"#;
+        assert_eq!(
+            find_html_redirect(html).as_deref(),
+            Some("https://example.com/c")
+        );
+
+        let html = r#"<script>location.replace('https://example.com/d')</script>"#;
+        assert_eq!(
+            find_html_redirect(html).as_deref(),
+            Some("https://example.com/d")
+        );
+    }
+
+    #[test]
+    fn no_redirect() {
+        let html = r#"<meta http-equiv="refresh" content="30"><p>location</p>"#;
+        assert_eq!(find_html_redirect(html), None);
+    }
+}
@@ -0,0 +1,268 @@
+use std::fmt::Display;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+
+use super::sb3::{project::Sb3BlockEntry, Sb3Project};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExtensionBundle {
+    /// 放进 .sb3 的 extensions/ 目录
+    Archive,
+    /// 放进 .sb3 旁的 <标题>.extensions/ 目录
+    Sidecar,
+}
+
+/// TurboWarp 可以直接从 `data:` 链接加载插件，离线打开作品时不再依赖原地址
+pub fn to_data_url<S: AsRef<[u8]>>(source: S) -> String {
+    ["data:text/javascript;base64,", &STANDARD.encode(source)].concat()
+}
+
+/// Scratch 自带的插件
+const SCRATCH_EXTENSIONS: [&str; 12] = [
+    "pen",
+    "wedo2",
+    "music",
+    "microbit",
+    "text2speech",
+    "translate",
+    "videoSensing",
+    "ev3",
+    "makeymakey",
+    "boost",
+    "gdxfor",
+    "faceSensing",
+];
+const TURBOWARP_EXTENSIONS: [&str; 1] = ["tw"];
+/// Gandi IDE 自带的插件，以及插件库中不需要地址即可加载的插件
+const GANDI_EXTENSIONS: [&str; 4] = [
+    "GandiQuake",
+    "WitCatInput",
+    "WitCatMouse",
+    "WitCatFileHelper",
+];
+const PENGUINMOD_EXTENSIONS: [&str; 34] = [
+    "tw",
+    "jgJSON",
+    "jgRuntime",
+    "jgFiles",
+    "jgIframe",
+    "jgTween",
+    "jgWebsiteRequests",
+    "jgPrism",
+    "jgStorage",
+    "jgTimers",
+    "jgClones",
+    "jgDebugging",
+    "jgEasySave",
+    "jgAdvancedText",
+    "jgAnimation",
+    "jgExtendedAudio",
+    "jgScratchAuthenticate",
+    "jgPackagerApplications",
+    "jgCostumeDrawing",
+    "jgVr",
+    "pmEventsExpansion",
+    "pmControlsExpansion",
+    "pmOperatorsExpansion",
+    "pmSensingExpansion",
+    "pmMotionExpansion",
+    "pmLooksExpansion",
+    "pmInlineBlocks",
+    "pmCamera",
+    "canvas",
+    "jwUnite",
+    "jwArray",
+    "jwTargets",
+    "jwXml",
+    "jwProto",
+];
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum TargetPlayer {
+    #[default]
+    Scratch,
+    #[value(name = "turbowarp")]
+    TurboWarp,
+    /// 共创世界使用的 Gandi IDE
+    Gandi,
+    Clipcc,
+    #[value(name = "penguinmod")]
+    PenguinMod,
+}
+impl TargetPlayer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TargetPlayer::Scratch => "Scratch",
+            TargetPlayer::TurboWarp => "TurboWarp",
+            TargetPlayer::Gandi => "Gandi IDE",
+            TargetPlayer::Clipcc => "Clipcc",
+            TargetPlayer::PenguinMod => "PenguinMod",
+        }
+    }
+
+    fn buildin_extensions(&self) -> &'static [&'static str] {
+        match self {
+            TargetPlayer::TurboWarp => &TURBOWARP_EXTENSIONS,
+            TargetPlayer::PenguinMod => &PENGUINMOD_EXTENSIONS,
+            TargetPlayer::Gandi => &GANDI_EXTENSIONS,
+            // Clipcc 的 .ccx 插件需要用户自行安装，不算作自带插件
+            TargetPlayer::Scratch | TargetPlayer::Clipcc => &[],
+        }
+    }
+    /// 能否通过 `extensionURLs` 加载自定义插件
+    fn loads_custom_extensions(&self) -> bool {
+        matches!(
+            self,
+            TargetPlayer::TurboWarp | TargetPlayer::Gandi | TargetPlayer::PenguinMod
+        )
+    }
+
+    pub fn supports(&self, id: &str, has_url: bool) -> bool {
+        SCRATCH_EXTENSIONS.contains(&id)
+            || self.buildin_extensions().contains(&id)
+            || (has_url && self.loads_custom_extensions())
+    }
+}
+
+pub struct IncompatibleExtension {
+    pub id: String,
+    /// 用到的该插件积木
+    pub opcodes: Vec<String>,
+    /// 用到该插件的角色
+    pub targets: Vec<String>,
+}
+
+pub struct CompatibilityReport {
+    pub player: TargetPlayer,
+    pub extensions: Vec<IncompatibleExtension>,
+}
+impl CompatibilityReport {
+    /// 找出作品在指定播放器中无法加载的插件，全部兼容时返回 `None`
+    pub fn check(project: &Sb3Project, player: TargetPlayer) -> Option<Self> {
+        let extensions: Vec<IncompatibleExtension> = project
+            .extensions
+            .iter()
+            .filter(|id| {
+                let has_url = project
+                    .extension_urls
+                    .as_ref()
+                    .is_some_and(|urls| urls.contains_key(*id));
+                !player.supports(id, has_url)
+            })
+            .map(|id| {
+                let mut opcodes = Vec::new();
+                let mut targets = Vec::new();
+                for target in project.targets.iter() {
+                    let blocks = target.blocks.values().filter_map(Sb3BlockEntry::as_block);
+                    for block in blocks.filter(|block| extension_of(&block.opcode) == id) {
+                        if !opcodes.contains(&block.opcode) {
+                            opcodes.push(block.opcode.clone());
+                        }
+                        if !targets.contains(&target.name) {
+                            targets.push(target.name.clone());
+                        }
+                    }
+                }
+
+                IncompatibleExtension {
+                    id: id.clone(),
+                    opcodes,
+                    targets,
+                }
+            })
+            .collect();
+
+        match extensions.len() {
+            0 => None,
+            _ => Some(CompatibilityReport { player, extensions }),
+        }
+    }
+}
+impl Display for CompatibilityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "⚠️ 下载项目中有 {} 不兼容的插件:", self.player.name())?;
+        for extension in self.extensions.iter() {
+            write!(f, "\n    {}", extension.id)?;
+            if !extension.opcodes.is_empty() {
+                write!(
+                    f,
+                    " - 积木: {} (角色: {})",
+                    extension.opcodes.join(", "),
+                    extension.targets.join(", ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 插件积木的 opcode 以插件 ID 开头，如 `pen_clear`
+pub fn extension_of(opcode: &str) -> &str {
+    opcode.split_once('_').map_or(opcode, |(id, _)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supports_per_player() {
+        for player in TargetPlayer::value_variants() {
+            assert!(player.supports("pen", false));
+            assert!(!player.supports("unknownExtension", false));
+        }
+
+        assert!(!TargetPlayer::Scratch.supports("tw", false));
+        assert!(TargetPlayer::TurboWarp.supports("tw", false));
+        assert!(TargetPlayer::PenguinMod.supports("jgJSON", false));
+        assert!(!TargetPlayer::TurboWarp.supports("jgJSON", false));
+        assert!(TargetPlayer::Gandi.supports("GandiQuake", false));
+        assert!(!TargetPlayer::Clipcc.supports("clipteam.example", false));
+    }
+
+    #[test]
+    fn custom_extensions_need_url() {
+        for player in [
+            TargetPlayer::TurboWarp,
+            TargetPlayer::Gandi,
+            TargetPlayer::PenguinMod,
+        ] {
+            assert!(player.supports("custom", true));
+        }
+        assert!(!TargetPlayer::Scratch.supports("custom", true));
+        assert!(!TargetPlayer::Clipcc.supports("custom", true));
+    }
+
+    #[test]
+    fn check_reports_blocks_and_targets() {
+        let project = serde_json::from_value::<Sb3Project>(serde_json::json!({
+            "targets": [
+                {
+                    "isStage": true,
+                    "name": "Stage",
+                    "blocks": { "a": { "opcode": "pen_clear" } }
+                },
+                {
+                    "name": "Sprite1",
+                    "blocks": {
+                        "b": { "opcode": "tw_getLastKeyPressed" },
+                        "c": { "opcode": "custom_run" },
+                        "d": { "opcode": "custom_run" }
+                    }
+                }
+            ],
+            "extensions": ["pen", "tw", "custom"],
+            "extensionURLs": { "custom": "https://example.com/custom.js" }
+        }))
+        .unwrap();
+
+        assert!(CompatibilityReport::check(&project, TargetPlayer::TurboWarp).is_none());
+
+        let report = CompatibilityReport::check(&project, TargetPlayer::Scratch).unwrap();
+        let ids: Vec<&str> = report.extensions.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, ["tw", "custom"]);
+        assert_eq!(report.extensions[1].opcodes, ["custom_run"]);
+        assert_eq!(report.extensions[1].targets, ["Sprite1"]);
+    }
+}
@@ -0,0 +1,99 @@
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum LineageFormat {
+    /// 写入 <标题>.lineage.json
+    #[default]
+    Json,
+    /// 写入 <标题>.lineage.dot，可用 Graphviz 绘制
+    Dot,
+}
+impl LineageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LineageFormat::Json => "lineage.json",
+            LineageFormat::Dot => "lineage.dot",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LineageNode {
+    pub id: String,
+    pub title: Option<String>,
+    pub source: String,
+}
+
+#[derive(Serialize, PartialEq)]
+pub struct LineageEdge {
+    pub parent: String,
+    pub child: String,
+}
+
+/// 作品之间的改编关系，边由原作品指向改编作品
+#[derive(Serialize, Default)]
+pub struct Lineage {
+    #[serde(serialize_with = "serialize_nodes")]
+    nodes: IndexMap<String, LineageNode>,
+    edges: Vec<LineageEdge>,
+}
+
+fn serialize_nodes<S: serde::Serializer>(
+    nodes: &IndexMap<String, LineageNode>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(nodes.values())
+}
+
+impl Lineage {
+    pub fn contains(&self, id: &str) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    /// 再次加入同一作品时更新其信息，位置保持不变
+    pub fn add_node(&mut self, node: LineageNode) {
+        self.nodes.insert(node.id.clone(), node);
+    }
+    pub fn add_edge(&mut self, parent: &str, child: &str) {
+        let edge = LineageEdge {
+            parent: parent.to_owned(),
+            child: child.to_owned(),
+        };
+
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    pub fn render(&self, format: LineageFormat) -> serde_json::Result<String> {
+        match format {
+            LineageFormat::Json => serde_json::to_string_pretty(self),
+            LineageFormat::Dot => Ok(self.to_dot()),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let quote = |s: &str| {
+            let s = s.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("\"{}\"", s.replace('\n', "\\n"))
+        };
+
+        let mut dot = String::from("digraph lineage {\n");
+        for node in self.nodes.values() {
+            let label = match &node.title {
+                Some(title) => format!("{}\n{}", title, node.id),
+                None => node.id.clone(),
+            };
+            dot += &format!("    {} [label={}];\n", quote(&node.id), quote(&label));
+        }
+        for edge in &self.edges {
+            dot += &format!("    {} -> {};\n", quote(&edge.parent), quote(&edge.child));
+        }
+        dot.push('}');
+        dot.push('\n');
+
+        dot
+    }
+}
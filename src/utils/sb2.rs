@@ -0,0 +1,209 @@
+//! Scratch 2.0 作品
+//!
+//! .sb2 中的资源不以 md5 命名，而是按 `baseLayerID`、`soundID` 等编号存为 `<编号>.<格式>`。
+use anyhow::Result;
+use serde::{de::IgnoredAny, Deserialize};
+use serde_json::Value;
+
+use super::sb3::{Sb3Asset, Sb3AssetKind};
+
+/// 造型中的图层 (md5 字段, 编号字段)
+const SB2_COSTUME_LAYERS: [(&str, &str); 2] = [
+    ("baseLayerMD5", "baseLayerID"),
+    ("textLayerMD5", "textLayerID"),
+];
+
+/// 只检查顶层的键，不为 Scratch 3.0 作品构建完整的 JSON
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Sb2Probe {
+    targets: Option<IgnoredAny>,
+    obj_name: Option<IgnoredAny>,
+}
+
+pub struct Sb2Project(Value);
+impl Sb2Project {
+    /// project.json 是 Scratch 2.0 格式时返回 `Some`
+    pub fn detect<J: AsRef<[u8]>>(json: J) -> Option<Self> {
+        let probe = serde_json::from_slice::<Sb2Probe>(json.as_ref()).ok()?;
+        if probe.targets.is_some() || probe.obj_name.is_none() {
+            return None;
+        }
+
+        serde_json::from_slice(json.as_ref()).ok().map(Sb2Project)
+    }
+
+    /// 按 .sb2 的规则为图片和声音重新编号，返回需要打包的资源
+    pub fn assign_ids(&mut self) -> Vec<Sb3Asset> {
+        let mut images = Sb2Assets::new(Sb3AssetKind::Costume);
+        let mut sounds = Sb2Assets::new(Sb3AssetKind::Sound);
+
+        let stage = &mut self.0;
+        images.assign(stage, "penLayerMD5", "penLayerID");
+        assign_object(stage, &mut images, &mut sounds);
+
+        let children = stage.get_mut("children").and_then(Value::as_array_mut);
+        for child in children.into_iter().flatten() {
+            assign_object(child, &mut images, &mut sounds);
+        }
+
+        images.into_assets().chain(sounds.into_assets()).collect()
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(&self.0)?)
+    }
+}
+
+/// 舞台或角色的造型与声音
+fn assign_object(object: &mut Value, images: &mut Sb2Assets, sounds: &mut Sb2Assets) {
+    let costumes = object.get_mut("costumes").and_then(Value::as_array_mut);
+    for costume in costumes.into_iter().flatten() {
+        for (md5, id) in SB2_COSTUME_LAYERS {
+            images.assign(costume, md5, id);
+        }
+    }
+
+    let object_sounds = object.get_mut("sounds").and_then(Value::as_array_mut);
+    for sound in object_sounds.into_iter().flatten() {
+        sounds.assign(sound, "md5", "soundID");
+    }
+}
+
+/// 同一种资源按出现顺序编号，相同的文件共用一个编号
+struct Sb2Assets {
+    kind: Sb3AssetKind,
+    md5exts: Vec<String>,
+    /// 重新编号前的编号，从下载到的 .sb2 中读取资源时使用
+    sources: Vec<Option<i64>>,
+}
+impl Sb2Assets {
+    fn new(kind: Sb3AssetKind) -> Self {
+        Self {
+            kind,
+            md5exts: Vec::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    fn assign(&mut self, object: &mut Value, md5_key: &str, id_key: &str) {
+        let Some(md5ext) = object.get(md5_key).and_then(Value::as_str) else {
+            return;
+        };
+
+        let id = match self.md5exts.iter().position(|m| m == md5ext) {
+            Some(id) => id,
+            None => {
+                let source = object.get(id_key).and_then(Value::as_i64);
+                self.sources.push(source.filter(|id| *id >= 0));
+                self.md5exts.push(md5ext.to_owned());
+                self.md5exts.len() - 1
+            }
+        };
+        object[id_key] = id.into();
+    }
+
+    fn into_assets(self) -> impl Iterator<Item = Sb3Asset> {
+        let kind = self.kind;
+        self.md5exts
+            .into_iter()
+            .zip(self.sources)
+            .enumerate()
+            .map(move |(id, (md5ext, source))| {
+                let ext = md5ext.rsplit_once('.').map_or("", |(_, ext)| ext);
+                let entry = [id.to_string(), ext.to_owned()].join(".");
+                // 没有原编号时不能按新编号读取，以免取到另一个资源
+                let source = match source {
+                    Some(source) => [source.to_string(), ext.to_owned()].join("."),
+                    None => md5ext.clone(),
+                };
+
+                Sb3Asset {
+                    kind,
+                    md5ext,
+                    entry: Some(entry),
+                    source: Some(source),
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn project(value: Value) -> Sb2Project {
+        Sb2Project::detect(serde_json::to_vec(&value).unwrap()).unwrap()
+    }
+
+    fn entries(assets: &[Sb3Asset]) -> Vec<(&str, &str, &str)> {
+        assets
+            .iter()
+            .map(|asset| (asset.md5ext.as_str(), asset.entry(), asset.source()))
+            .collect()
+    }
+
+    #[test]
+    fn detect_only_sb2() {
+        assert!(Sb2Project::detect(br#"{"objName":"Stage","children":[]}"#).is_some());
+        assert!(Sb2Project::detect(br#"{"objName":"Stage","targets":[]}"#).is_none());
+        assert!(Sb2Project::detect(br#"{"targets":[]}"#).is_none());
+        assert!(Sb2Project::detect(b"not json").is_none());
+    }
+
+    #[test]
+    fn assign_ids_per_kind() {
+        let mut project = project(json!({
+            "objName": "Stage",
+            "penLayerMD5": "pen.png",
+            "penLayerID": 7,
+            "costumes": [{
+                "baseLayerMD5": "a.svg",
+                "baseLayerID": 3,
+                "textLayerMD5": "text.png",
+                "textLayerID": 4
+            }],
+            "sounds": [{ "md5": "a.wav", "soundID": 2 }],
+            "children": [{
+                "objName": "Sprite1",
+                "costumes": [
+                    { "baseLayerMD5": "a.svg", "baseLayerID": 3 },
+                    { "baseLayerMD5": "b.png", "baseLayerID": -1 }
+                ],
+                "sounds": [
+                    { "md5": "b.wav", "soundID": 0 },
+                    { "md5": "a.wav", "soundID": 2 }
+                ]
+            }]
+        }));
+
+        let assets = project.assign_ids();
+        assert_eq!(
+            entries(&assets),
+            [
+                ("pen.png", "0.png", "7.png"),
+                ("a.svg", "1.svg", "3.svg"),
+                ("text.png", "2.png", "4.png"),
+                // 没有原编号时按 md5 读取
+                ("b.png", "3.png", "b.png"),
+                ("a.wav", "0.wav", "2.wav"),
+                ("b.wav", "1.wav", "0.wav"),
+            ]
+        );
+
+        let json = serde_json::from_slice::<Value>(&project.to_json().unwrap()).unwrap();
+        assert_eq!(json["penLayerID"], 0);
+        assert_eq!(json["costumes"][0]["baseLayerID"], 1);
+        assert_eq!(json["costumes"][0]["textLayerID"], 2);
+        assert_eq!(json["sounds"][0]["soundID"], 0);
+
+        let sprite = &json["children"][0];
+        // 相同的文件共用一个编号
+        assert_eq!(sprite["costumes"][0]["baseLayerID"], 1);
+        assert_eq!(sprite["costumes"][1]["baseLayerID"], 3);
+        assert_eq!(sprite["sounds"][0]["soundID"], 1);
+        assert_eq!(sprite["sounds"][1]["soundID"], 0);
+    }
+}
@@ -1,52 +1,61 @@
-use std::io::{Cursor, Read, Seek, Write};
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read, Seek, Write},
+};
 
 use anyhow::{Ok, Result};
-use serde::Deserialize;
-use zip::{write::SimpleFileOptions, ZipArchive, ZipWriter};
-
-const BUILDIN_EXTENSIONS: [&str; 11] = [
-    "pen",
-    "wedo2",
-    "music",
-    "microbit",
-    "text2speech",
-    "translate",
-    "videoSensing",
-    "ev3",
-    "makeymakey",
-    "boost",
-    "gdxfor",
-];
-
-#[derive(Deserialize)]
-pub struct Sb3Project {
-    pub targets: Vec<Sb3Target>,
-    pub extensions: Vec<String>,
-}
-#[derive(Deserialize)]
-pub struct Sb3Target {
-    pub costumes: Vec<Sb3Asset>,
-    pub sounds: Vec<Sb3Asset>,
-}
-#[derive(Deserialize)]
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+pub use project::Sb3Project;
+
+pub mod compat;
+pub mod project;
+
 pub struct Sb3Asset {
-    #[serde(skip)]
     pub kind: Sb3AssetKind,
     pub md5ext: String,
+    /// 压缩包中的文件名，默认与 `md5ext` 相同
+    pub entry: Option<String>,
+    /// 下载到的压缩包中的文件名，默认与 `entry` 相同
+    pub source: Option<String>,
+}
+impl Sb3Asset {
+    pub fn entry(&self) -> &str {
+        self.entry.as_deref().unwrap_or(&self.md5ext)
+    }
+    pub fn source(&self) -> &str {
+        self.source.as_deref().unwrap_or(self.entry())
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub enum Sb3AssetKind {
+    #[default]
     Costume,
     Sound,
+    Font,
+    /// 社区编辑器自行引用的其它文件
+    Resource,
 }
-impl Default for Sb3AssetKind {
-    fn default() -> Self {
-        Self::Costume
-    }
+
+/// .sb3、.sb2 等 zip 压缩包的文件头
+pub const ZIP_MAGIC: [u8; 4] = [80, 75, 3, 4];
+
+/// 读取压缩包中的一个文件，文件不存在或压缩包损坏时返回 `None`
+pub fn read_archive_entry<B: AsRef<[u8]>>(archive: B, name: &str) -> Option<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(archive.as_ref())).ok()?;
+    let mut buf = Vec::new();
+    archive.by_name(name).ok()?.read_to_end(&mut buf).ok()?;
+
+    Some(buf)
 }
 
-pub struct Sb3Reader(pub Vec<u8>);
+pub struct Sb3Reader {
+    json: Vec<u8>,
+    project: OnceCell<Sb3Project>,
+}
 impl Sb3Reader {
     pub fn from_zip<B: AsRef<[u8]>>(buf: B) -> Result<Self> {
         let cursor = Cursor::new(buf.as_ref());
@@ -55,71 +64,249 @@ impl Sb3Reader {
 
         archive.by_name("project.json")?.read_to_end(&mut buf)?;
 
-        Ok(Sb3Reader(buf))
+        Ok(Sb3Reader::parse(buf))
     }
     pub fn parse<J: AsRef<[u8]>>(json: J) -> Self {
-        Sb3Reader(json.as_ref().into())
+        Sb3Reader {
+            json: json.as_ref().into(),
+            project: OnceCell::new(),
+        }
+    }
+    pub fn into_json(self) -> Vec<u8> {
+        self.json
     }
-    pub fn to_project(&self) -> Result<Sb3Project> {
-        Ok(serde_json::from_slice::<Sb3Project>(&self.0)?)
+    /// 解析结果会被缓存，多次调用只解析一次
+    pub fn to_project(&self) -> Result<&Sb3Project> {
+        self.project
+            .get_or_try_init(|| Ok(serde_json::from_slice::<Sb3Project>(&self.json)?))
     }
 
     pub fn assets(&self) -> Result<Vec<Sb3Asset>> {
-        let targets = self.to_project()?.targets;
-        let assets = targets.into_iter().flat_map(|target| {
-            fn with_kind(assets: Vec<Sb3Asset>, kind: Sb3AssetKind) -> Vec<Sb3Asset> {
-                assets
-                    .into_iter()
-                    .map(|asset| Sb3Asset { kind, ..asset })
-                    .collect()
-            }
-            let mut assets = with_kind(target.costumes, Sb3AssetKind::Costume);
-            assets.extend(with_kind(target.sounds, Sb3AssetKind::Sound));
-            assets
+        let project = self.to_project()?;
+        let assets = project.targets.iter().flat_map(|target| {
+            let costumes = target.costumes.iter().filter_map(|costume| {
+                Some(Sb3Asset {
+                    kind: Sb3AssetKind::Costume,
+                    md5ext: costume.file_name()?,
+                    entry: None,
+                    source: None,
+                })
+            });
+            let sounds = target.sounds.iter().filter_map(|sound| {
+                Some(Sb3Asset {
+                    kind: Sb3AssetKind::Sound,
+                    md5ext: sound.file_name()?,
+                    entry: None,
+                    source: None,
+                })
+            });
+
+            costumes.chain(sounds)
+        });
+        let fonts = project.custom_fonts.iter().flatten().filter_map(|font| {
+            Some(Sb3Asset {
+                kind: Sb3AssetKind::Font,
+                md5ext: font.file_name()?,
+                entry: None,
+                source: None,
+            })
         });
 
-        Ok(assets.collect())
+        Ok(assets.chain(fonts).collect())
     }
-    pub fn community_extensions(&self) -> Result<Option<Vec<String>>> {
-        let extensions = self.to_project()?.extensions;
-        let community_extensions: Vec<String> = extensions
-            .into_iter()
-            .filter(|extension| !BUILDIN_EXTENSIONS.contains(&extension.as_str()))
-            .collect();
+}
 
-        match community_extensions.len() {
-            0 => Ok(None),
-            _ => Ok(Some(community_extensions)),
-        }
+const DETERMINISTIC_PERMISSIONS: u32 = 0o644;
+const DETERMINISTIC_LEVEL: i64 = 6;
+/// 本身已经压缩过的资源格式，再次 deflate 几乎没有收益
+const PRECOMPRESSED_FORMATS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "mp3", "ogg"];
+
+#[derive(Clone, Copy, Default)]
+pub struct Sb3WriterOptions {
+    /// 固定条目顺序、修改时间和压缩参数，使同一作品的输出逐字节一致
+    pub deterministic: bool,
+    /// 以键排序、无空白的形式重写 project.json
+    pub canonical_json: bool,
+    pub compression: Sb3Compression,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Sb3Compression {
+    /// deflate 压缩等级，0-9 为普通压缩，10-264 使用 Zopfli 进行更强的压缩
+    pub level: Option<i64>,
+    /// 是否直接存储 png、mp3 等已压缩的资源
+    pub store_media: bool,
+}
+impl Sb3Compression {
+    fn is_precompressed(name: &str) -> bool {
+        name.rsplit_once('.')
+            .is_some_and(|(_, ext)| PRECOMPRESSED_FORMATS.contains(&ext.to_lowercase().as_str()))
     }
 }
 
 pub struct Sb3Writer<W: Write + Seek> {
     inner: ZipWriter<W>,
+    options: Sb3WriterOptions,
     assets: Vec<String>,
+    pending: BTreeMap<String, Vec<u8>>,
 }
 impl<W: Write + Seek> Sb3Writer<W> {
-    pub fn new(writer: W) -> Self {
+    pub fn new(writer: W, options: Sb3WriterOptions) -> Self {
         let inner = ZipWriter::new(writer);
         Sb3Writer {
             inner,
+            options,
             assets: Vec::new(),
+            pending: BTreeMap::new(),
         }
     }
 
     pub fn set_project_json<C: AsRef<[u8]>>(&mut self, json: C) -> Result<&mut Self> {
-        self.add_asset("project.json", json.as_ref())?;
+        match self.options.canonical_json {
+            true => self.add_asset("project.json", &canonicalize_json(json)?)?,
+            false => self.add_asset("project.json", json.as_ref())?,
+        };
         Ok(self)
     }
     pub fn add_asset(&mut self, name: &str, buf: &[u8]) -> Result<&mut Self> {
-        let inner = &mut self.inner;
         if !self.assets.contains(&name.to_owned()) {
-            inner.start_file(name, SimpleFileOptions::default())?;
-            inner.write_all(buf)?;
+            match self.options.deterministic {
+                true => {
+                    self.pending.insert(name.to_owned(), buf.to_vec());
+                }
+                false => self.write_entry(name, buf)?,
+            }
 
             self.assets.push(name.to_owned())
         }
 
         Ok(self)
     }
+    /// 写入缓存的条目 (project.json 在前，其余按名称排序) 并结束压缩包
+    pub fn finish(mut self) -> Result<W> {
+        let mut pending = std::mem::take(&mut self.pending);
+        if let Some(project) = pending.remove("project.json") {
+            self.write_entry("project.json", &project)?;
+        }
+        for (name, buf) in pending {
+            self.write_entry(&name, &buf)?;
+        }
+
+        Ok(self.inner.finish()?)
+    }
+
+    fn write_entry(&mut self, name: &str, buf: &[u8]) -> Result<()> {
+        let options = self.file_options(name);
+
+        self.inner.start_file(name, options)?;
+        self.inner.write_all(buf)?;
+        Ok(())
+    }
+    fn file_options(&self, name: &str) -> SimpleFileOptions {
+        let Sb3WriterOptions {
+            deterministic,
+            compression,
+            ..
+        } = self.options;
+        let mut options = SimpleFileOptions::default();
+
+        // zip 不接受 0 级 deflate，0 级即不压缩
+        if compression.level == Some(0)
+            || compression.store_media && Sb3Compression::is_precompressed(name)
+        {
+            options = options.compression_method(CompressionMethod::Stored);
+        } else if deterministic || compression.level.is_some() {
+            let level = compression.level.unwrap_or(DETERMINISTIC_LEVEL);
+            options = options
+                .compression_method(CompressionMethod::Deflated)
+                .compression_level(Some(level));
+        }
+
+        if deterministic {
+            options = options
+                // 1980-01-01 00:00:00
+                .last_modified_time(DateTime::default())
+                .unix_permissions(DETERMINISTIC_PERMISSIONS);
+        }
+
+        options
+    }
+}
+
+/// 将 JSON 重写为键排序、无多余空白的规范形式
+pub fn canonicalize_json<J: AsRef<[u8]>>(json: J) -> Result<Vec<u8>> {
+    fn sort_keys(value: Value) -> Value {
+        match value {
+            Value::Object(map) => {
+                let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+                Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(k, v)| (k, sort_keys(v)))
+                        .collect(),
+                )
+            }
+            Value::Array(items) => Value::Array(items.into_iter().map(sort_keys).collect()),
+            value => value,
+        }
+    }
+
+    let value = serde_json::from_slice::<Value>(json.as_ref())?;
+    Ok(serde_json::to_vec(&sort_keys(value))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn write(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let options = Sb3WriterOptions {
+            deterministic: true,
+            canonical_json: true,
+            ..Default::default()
+        };
+        let mut writer = Sb3Writer::new(Cursor::new(Vec::new()), options);
+        for (name, buf) in entries {
+            match *name {
+                "project.json" => writer.set_project_json(buf).unwrap(),
+                name => writer.add_asset(name, buf).unwrap(),
+            };
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn deterministic_output_ignores_entry_order() {
+        let project: &[u8] = br#"{"targets":[],"meta":{"semver":"3.0.0"}}"#;
+        let reordered: &[u8] = br#"{ "meta": { "semver": "3.0.0" }, "targets": [] }"#;
+        let costume: &[u8] = b"<svg></svg>";
+        let sound: &[u8] = b"RIFF";
+
+        let a = write(&[
+            ("project.json", project),
+            ("a.svg", costume),
+            ("b.wav", sound),
+        ]);
+        let b = write(&[
+            ("b.wav", sound),
+            ("a.svg", costume),
+            ("project.json", reordered),
+        ]);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn canonicalize_json_sorts_nested_keys() {
+        let json = br#"{ "b": [{ "z": 1, "a": { "y": 2, "x": 3 } }], "a": null }"#;
+
+        assert_eq!(
+            canonicalize_json(json).unwrap(),
+            br#"{"a":null,"b":[{"a":{"x":3,"y":2},"z":1}]}"#
+        );
+    }
 }
@@ -0,0 +1,391 @@
+//! 移除播放器不支持的插件积木，让作品至少能够打开
+use clap::ValueEnum;
+use serde_json::{Map, Number, Value};
+use std::fmt::Display;
+
+use super::project::{Sb3Block, Sb3Comment, Sb3Target};
+use super::Sb3Project;
+use crate::utils::extensions::{extension_of, CompatibilityReport, TargetPlayer};
+
+const COMMENT_ID_PREFIX: &str = "rabdog-compat-";
+const COMMENT_SIZE: u64 = 200;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum CompatTransform {
+    /// 删除不兼容的积木，前后的积木重新接上，被删除的值积木退回为默认值
+    Strip,
+    /// 同 strip，并在原位置留下注释说明删除了哪些积木
+    Annotate,
+}
+
+pub struct CompatTransformReport {
+    pub player: TargetPlayer,
+    pub extensions: Vec<String>,
+    /// 不兼容插件的积木数
+    pub extension_blocks: usize,
+    /// 实际删除的积木数，包括嵌在插件积木中的其它积木
+    pub blocks: usize,
+}
+impl Display for CompatTransformReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "为兼容 {} 移除了 {} 个插件积木 (连同其中的积木共 {} 个) 与插件: {}",
+            self.player.name(),
+            self.extension_blocks,
+            self.blocks,
+            self.extensions.join(", ")
+        )
+    }
+}
+
+/// 被删除的积木留下的注释 (挂靠的积木, 删除的 opcode, 没有可挂靠积木时的位置)
+struct Note {
+    anchor: Option<String>,
+    opcode: String,
+    position: (Option<Number>, Option<Number>),
+}
+
+impl CompatTransform {
+    /// 移除不兼容的积木、监视器与插件声明，作品全部兼容时返回 `None`
+    pub fn apply(
+        &self,
+        project: &mut Sb3Project,
+        player: TargetPlayer,
+    ) -> Option<CompatTransformReport> {
+        let report = CompatibilityReport::check(project, player)?;
+        let extensions: Vec<String> = report.extensions.into_iter().map(|e| e.id).collect();
+        let unsupported = |opcode: &str| extensions.iter().any(|id| id == extension_of(opcode));
+
+        let (extension_blocks, blocks) = project
+            .targets
+            .iter_mut()
+            .map(|target| self.strip_target(target, &unsupported))
+            .fold((0, 0), |(a, b), (c, d)| (a + c, b + d));
+
+        project.extensions.retain(|id| !extensions.contains(id));
+        if let Some(urls) = project.extension_urls.as_mut() {
+            urls.retain(|id, _| !extensions.contains(id));
+        }
+        project
+            .monitors
+            .retain(|monitor| !unsupported(&monitor.opcode));
+
+        Some(CompatTransformReport {
+            player,
+            extensions,
+            extension_blocks,
+            blocks,
+        })
+    }
+
+    /// 返回 (不兼容的积木数, 删除的积木数)
+    fn strip_target(
+        &self,
+        target: &mut Sb3Target,
+        unsupported: &impl Fn(&str) -> bool,
+    ) -> (usize, usize) {
+        let doomed: Vec<String> = target
+            .blocks
+            .iter()
+            .filter(|(_, entry)| entry.as_block().is_some_and(|b| unsupported(&b.opcode)))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        let mut removed = Vec::new();
+        let mut notes = Vec::new();
+        for id in doomed.iter() {
+            // 已经随外层的不兼容积木一起删除
+            if removed.contains(id) {
+                continue;
+            }
+            let Some(block) = block(target, id).cloned() else {
+                continue;
+            };
+
+            detach(target, id, &block);
+            collect_subtree(target, id, &mut removed);
+            notes.push(Note {
+                anchor: block.parent.or(block.next),
+                opcode: block.opcode,
+                position: (block.x, block.y),
+            });
+        }
+
+        for id in removed.iter() {
+            target.blocks.shift_remove(id);
+        }
+        for comment in target.comments.values_mut() {
+            if comment
+                .block_id
+                .as_ref()
+                .is_some_and(|id| removed.contains(id))
+            {
+                comment.block_id = None;
+            }
+        }
+        if *self == CompatTransform::Annotate {
+            notes.into_iter().for_each(|note| annotate(target, note));
+        }
+
+        (doomed.len(), removed.len())
+    }
+}
+
+fn block<'a>(target: &'a Sb3Target, id: &str) -> Option<&'a Sb3Block> {
+    target.blocks.get(id).and_then(|entry| entry.as_block())
+}
+fn block_mut<'a>(target: &'a mut Sb3Target, id: &str) -> Option<&'a mut Sb3Block> {
+    target
+        .blocks
+        .get_mut(id)
+        .and_then(|entry| entry.as_block_mut())
+}
+
+/// 把积木从脚本中摘下，前后的积木重新接上
+fn detach(target: &mut Sb3Target, id: &str, removing: &Sb3Block) {
+    let Sb3Block { next, parent, .. } = removing;
+
+    if let Some(next) = next.as_deref().and_then(|next| block_mut(target, next)) {
+        next.parent = parent.clone();
+        if parent.is_none() {
+            (next.top_level, next.x, next.y) = (true, removing.x.clone(), removing.y.clone());
+        }
+    }
+
+    let Some(parent) = parent
+        .as_deref()
+        .and_then(|parent| block_mut(target, parent))
+    else {
+        return;
+    };
+    if parent.next.as_deref() == Some(id) {
+        parent.next = next.clone();
+        return;
+    }
+
+    let id = Value::String(id.to_owned());
+    parent.inputs.retain(|_, input| {
+        if input.get(1) != Some(&id) {
+            return true;
+        }
+
+        match (next, input.get(2)) {
+            // 嵌在 C 形积木中的脚本，由后一个积木顶上
+            (Some(next), _) => input[1] = Value::String(next.clone()),
+            // 被遮挡的影子积木重新露出来
+            (None, Some(shadow)) if !shadow.is_null() => *input = vec![1.into(), shadow.clone()],
+            (None, _) => return false,
+        }
+        true
+    });
+}
+
+/// 积木本身以及输入中的所有积木 (不包括其后的积木)
+fn collect_subtree(target: &Sb3Target, id: &str, out: &mut Vec<String>) {
+    if out.iter().any(|removed| removed == id) {
+        return;
+    }
+    out.push(id.to_owned());
+
+    let Some(block) = block(target, id) else {
+        return;
+    };
+    let children = block.inputs.values().flat_map(|input| input.iter().skip(1));
+    for child in children.filter_map(Value::as_str) {
+        let mut current = Some(child.to_owned());
+        while let Some(id) = current.filter(|id| target.blocks.contains_key(id)) {
+            collect_subtree(target, &id, out);
+            current = self::block(target, &id).and_then(|block| block.next.clone());
+        }
+    }
+}
+
+fn annotate(target: &mut Sb3Target, note: Note) {
+    let text = ["rabdog: 已移除不兼容的积木 ", &note.opcode].concat();
+    // 挂靠的积木也被删除时，由外层积木的注释说明
+    if note
+        .anchor
+        .as_deref()
+        .is_some_and(|id| block(target, id).is_none())
+    {
+        return;
+    }
+    let anchor = note.anchor;
+
+    let existing = anchor
+        .as_deref()
+        .and_then(|id| block(target, id))
+        .and_then(|block| block.comment.clone());
+    if let Some(comment) = existing.and_then(|id| target.comments.get_mut(&id)) {
+        comment.text = [&comment.text, "\n", &text].concat();
+        return;
+    }
+
+    let comment_id = [COMMENT_ID_PREFIX, &target.comments.len().to_string()].concat();
+    let (x, y) = match anchor {
+        Some(_) => (Some(Number::from(0)), Some(Number::from(0))),
+        None => note.position,
+    };
+    if let Some(block) = anchor.as_deref().and_then(|id| block_mut(target, id)) {
+        block.comment = Some(comment_id.clone());
+    }
+
+    let comment = Sb3Comment {
+        block_id: anchor,
+        x,
+        y,
+        width: Some(COMMENT_SIZE.into()),
+        height: Some(COMMENT_SIZE.into()),
+        minimized: false,
+        text,
+        extra: Map::new(),
+    };
+    target.comments.insert(comment_id, comment);
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// 只有舞台的作品，`foo` 是 Scratch 不支持的插件
+    fn project(blocks: Value) -> Sb3Project {
+        serde_json::from_value(json!({
+            "targets": [{ "isStage": true, "name": "Stage", "blocks": blocks }],
+            "extensions": ["foo"],
+        }))
+        .unwrap()
+    }
+    fn strip(project: &mut Sb3Project) -> CompatTransformReport {
+        CompatTransform::Strip
+            .apply(project, TargetPlayer::Scratch)
+            .unwrap()
+    }
+    fn get<'a>(project: &'a Sb3Project, id: &str) -> &'a Sb3Block {
+        block(&project.targets[0], id).unwrap()
+    }
+
+    #[test]
+    fn mid_stack_block() {
+        let mut project = project(json!({
+            "hat": { "opcode": "event_whenflagclicked", "next": "foo", "topLevel": true, "x": 0, "y": 0 },
+            "foo": { "opcode": "foo_do", "next": "move", "parent": "hat" },
+            "move": { "opcode": "motion_movesteps", "parent": "foo" },
+        }));
+        let report = strip(&mut project);
+
+        assert_eq!((report.extension_blocks, report.blocks), (1, 1));
+        assert_eq!(get(&project, "hat").next.as_deref(), Some("move"));
+        assert_eq!(get(&project, "move").parent.as_deref(), Some("hat"));
+        assert!(project.extensions.is_empty());
+    }
+
+    #[test]
+    fn substack_block() {
+        let mut project = project(json!({
+            "loop": { "opcode": "control_forever", "inputs": { "SUBSTACK": [2, "foo"] }, "topLevel": true },
+            "foo": { "opcode": "foo_do", "next": "move", "parent": "loop" },
+            "move": { "opcode": "motion_movesteps", "parent": "foo" },
+            "if": { "opcode": "control_if", "inputs": { "SUBSTACK": [2, "bar"] }, "topLevel": true },
+            "bar": { "opcode": "foo_do", "parent": "if" },
+        }));
+        strip(&mut project);
+
+        assert_eq!(
+            get(&project, "loop").inputs["SUBSTACK"],
+            [json!(2), json!("move")]
+        );
+        assert_eq!(get(&project, "move").parent.as_deref(), Some("loop"));
+        // C 形积木中只有这一个积木时，整个输入被删除
+        assert!(!get(&project, "if").inputs.contains_key("SUBSTACK"));
+    }
+
+    #[test]
+    fn reporter_with_shadow() {
+        let mut project = project(json!({
+            "move": {
+                "opcode": "motion_movesteps",
+                "inputs": { "STEPS": [3, "foo", [4, "10"]] },
+                "topLevel": true,
+            },
+            "foo": { "opcode": "foo_value", "parent": "move" },
+            "say": {
+                "opcode": "looks_say",
+                "inputs": { "MESSAGE": [3, "bar", "text"] },
+                "topLevel": true,
+            },
+            "bar": { "opcode": "foo_value", "parent": "say" },
+            "text": { "opcode": "text", "parent": "say", "shadow": true },
+        }));
+        strip(&mut project);
+
+        assert_eq!(
+            get(&project, "move").inputs["STEPS"],
+            [json!(1), json!([4, "10"])]
+        );
+        assert_eq!(
+            get(&project, "say").inputs["MESSAGE"],
+            [json!(1), json!("text")]
+        );
+        assert!(block(&project.targets[0], "text").is_some());
+    }
+
+    #[test]
+    fn hat_block() {
+        let mut project = project(json!({
+            "hat": { "opcode": "foo_when", "next": "move", "topLevel": true, "x": 12, "y": 34 },
+            "move": { "opcode": "motion_movesteps", "parent": "hat" },
+        }));
+        strip(&mut project);
+
+        let next = get(&project, "move");
+        assert!(next.top_level);
+        assert_eq!(next.parent, None);
+        assert_eq!(
+            (next.x.clone(), next.y.clone()),
+            (Some(12.into()), Some(34.into()))
+        );
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let mut project = project(json!({
+            "outer": {
+                "opcode": "foo_repeat",
+                "inputs": { "TIMES": [3, "inner", [4, "3"]], "SUBSTACK": [2, "if"] },
+                "topLevel": true,
+            },
+            "inner": { "opcode": "foo_value", "parent": "outer" },
+            "if": { "opcode": "control_if", "inputs": { "SUBSTACK": [2, "move"] }, "parent": "outer" },
+            "move": { "opcode": "motion_movesteps", "parent": "if" },
+        }));
+        let comment = json!({ "blockId": "move", "x": 0, "y": 0, "text": "note" });
+        project.targets[0]
+            .comments
+            .insert("c".into(), serde_json::from_value(comment).unwrap());
+        let report = strip(&mut project);
+
+        // 嵌在 C 形积木中的普通积木也随之删除
+        assert_eq!((report.extension_blocks, report.blocks), (2, 4));
+        assert!(project.targets[0].blocks.is_empty());
+        assert_eq!(project.targets[0].comments["c"].block_id, None);
+    }
+
+    #[test]
+    fn annotate_removed_block() {
+        let mut project = project(json!({
+            "hat": { "opcode": "event_whenflagclicked", "next": "foo", "topLevel": true },
+            "foo": { "opcode": "foo_do", "parent": "hat" },
+        }));
+        CompatTransform::Annotate
+            .apply(&mut project, TargetPlayer::Scratch)
+            .unwrap();
+
+        let comment = get(&project, "hat").comment.clone().unwrap();
+        assert!(project.targets[0].comments[&comment]
+            .text
+            .contains("foo_do"));
+    }
+}
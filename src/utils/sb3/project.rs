@@ -0,0 +1,580 @@
+//! Scratch 3 project.json 的类型化模型
+//!
+//! 未列出的字段会保存在各结构体的 `extra` 中，社区自行扩展的字段在读写后不会丢失。
+//! Scratch 总会写出的字段 (如 `isStage`、`next`、`monitors`) 缺失时按 scratch-vm 的默认值补上。
+use anyhow::{anyhow, Error, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Project {
+    pub targets: Vec<Sb3Target>,
+    #[serde(default)]
+    pub monitors: Vec<Sb3Monitor>,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(
+        rename = "extensionURLs",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extension_urls: Option<IndexMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Sb3Meta>,
+    /// TurboWarp 的自定义字体
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_fonts: Option<Vec<Sb3Font>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+impl Sb3Project {
+    /// 在 `md5ext` 与 `assetId`、`dataFormat` 之间互相补全造型、声音和字体的文件名，返回是否有改动
+    pub fn fill_md5ext(&mut self) -> bool {
+        let mut changed = false;
+        for target in self.targets.iter_mut() {
+            for costume in target.costumes.iter_mut() {
+                changed |= fill_asset_fields(
+                    &mut costume.md5ext,
+                    &mut costume.asset_id,
+                    &mut costume.data_format,
+                );
+            }
+            for sound in target.sounds.iter_mut() {
+                changed |= fill_asset_fields(
+                    &mut sound.md5ext,
+                    &mut sound.asset_id,
+                    &mut sound.data_format,
+                );
+            }
+        }
+        for font in self.custom_fonts.iter_mut().flatten() {
+            changed |=
+                fill_asset_fields(&mut font.md5ext, &mut font.asset_id, &mut font.data_format);
+        }
+
+        changed
+    }
+
+    /// 用快照中的值覆盖舞台上的同名云变量，返回是否有改动
+    pub fn set_cloud_values(&mut self, values: &IndexMap<String, Value>) -> bool {
+        let mut changed = false;
+        for target in self.targets.iter_mut().filter(|t| t.is_stage) {
+            for variable in target.variables.values_mut().filter(|v| v.is_cloud()) {
+                if let Some(value) = values.get(&variable.name) {
+                    variable.value = value.clone();
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Target {
+    #[serde(default)]
+    pub is_stage: bool,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub variables: IndexMap<String, Sb3Variable>,
+    #[serde(default)]
+    pub lists: IndexMap<String, Sb3List>,
+    #[serde(default)]
+    pub broadcasts: IndexMap<String, String>,
+    #[serde(default)]
+    pub blocks: IndexMap<String, Sb3BlockEntry>,
+    #[serde(default)]
+    pub comments: IndexMap<String, Sb3Comment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_costume: Option<Number>,
+    #[serde(default)]
+    pub costumes: Vec<Sb3Costume>,
+    #[serde(default)]
+    pub sounds: Vec<Sb3Sound>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer_order: Option<Number>,
+
+    // 舞台独有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tempo: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_transparency: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_state: Option<String>,
+
+    // 角色独有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visible: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draggable: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation_style: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// `[名称, 值]`，云变量为 `[名称, 值, true]`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "Vec<Value>", into = "Vec<Value>")]
+pub struct Sb3Variable {
+    pub name: String,
+    pub value: Value,
+    /// 名称与值之后的元素，原样保留
+    pub rest: Vec<Value>,
+}
+impl Sb3Variable {
+    pub fn is_cloud(&self) -> bool {
+        matches!(self.rest.first(), Some(Value::Bool(true)))
+    }
+}
+impl TryFrom<Vec<Value>> for Sb3Variable {
+    type Error = Error;
+
+    fn try_from(items: Vec<Value>) -> Result<Self> {
+        let (name, value, rest) = split_named(items)?;
+        let value = value.unwrap_or(Value::from(0));
+
+        Ok(Sb3Variable { name, value, rest })
+    }
+}
+impl From<Sb3Variable> for Vec<Value> {
+    fn from(variable: Sb3Variable) -> Self {
+        [Value::String(variable.name), variable.value]
+            .into_iter()
+            .chain(variable.rest)
+            .collect()
+    }
+}
+
+/// `[名称, [项...]]`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "Vec<Value>", into = "Vec<Value>")]
+pub struct Sb3List {
+    pub name: String,
+    pub items: Vec<Value>,
+    pub rest: Vec<Value>,
+}
+impl TryFrom<Vec<Value>> for Sb3List {
+    type Error = Error;
+
+    fn try_from(items: Vec<Value>) -> Result<Self> {
+        let (name, items, rest) = split_named(items)?;
+        let Value::Array(items) = items.unwrap_or(Value::Array(Vec::new())) else {
+            return Err(anyhow!("list items should be an array"));
+        };
+
+        Ok(Sb3List { name, items, rest })
+    }
+}
+impl From<Sb3List> for Vec<Value> {
+    fn from(list: Sb3List) -> Self {
+        [Value::String(list.name), Value::Array(list.items)]
+            .into_iter()
+            .chain(list.rest)
+            .collect()
+    }
+}
+
+/// 值缺失时返回 `None`，由调用方按 scratch-vm 的默认值补上
+fn split_named(items: Vec<Value>) -> Result<(String, Option<Value>, Vec<Value>)> {
+    let mut items = items.into_iter();
+    let name = match items.next() {
+        Some(Value::String(name)) => name,
+        _ => return Err(anyhow!("missing name")),
+    };
+    let value = items.next();
+
+    Ok((name, value, items.collect()))
+}
+
+/// `blocks` 中的条目，顶层的变量、列表积木会被压缩成数组
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Sb3BlockEntry {
+    Block(Box<Sb3Block>),
+    Primitive(Vec<Value>),
+}
+impl Sb3BlockEntry {
+    pub fn as_block(&self) -> Option<&Sb3Block> {
+        match self {
+            Sb3BlockEntry::Block(block) => Some(block),
+            Sb3BlockEntry::Primitive(_) => None,
+        }
+    }
+    pub fn as_block_mut(&mut self) -> Option<&mut Sb3Block> {
+        match self {
+            Sb3BlockEntry::Block(block) => Some(block),
+            Sb3BlockEntry::Primitive(_) => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Block {
+    pub opcode: String,
+    #[serde(default)]
+    pub next: Option<String>,
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// `[遮挡类型, 积木或值, (被遮挡的影子积木)]`
+    #[serde(default)]
+    pub inputs: IndexMap<String, Vec<Value>>,
+    /// `[值, (ID)]`
+    #[serde(default)]
+    pub fields: IndexMap<String, Vec<Value>>,
+    #[serde(default)]
+    pub shadow: bool,
+    #[serde(default)]
+    pub top_level: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutation: Option<Map<String, Value>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Comment {
+    #[serde(default)]
+    pub block_id: Option<String>,
+    #[serde(default)]
+    pub x: Option<Number>,
+    #[serde(default)]
+    pub y: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<Number>,
+    #[serde(default)]
+    pub minimized: bool,
+    #[serde(default)]
+    pub text: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Costume {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5ext: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bitmap_resolution: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation_center_x: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation_center_y: Option<Number>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Sound {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5ext: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_count: Option<Number>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Sb3Costume {
+    pub fn file_name(&self) -> Option<String> {
+        asset_file_name(&self.md5ext, &self.asset_id, &self.data_format)
+    }
+}
+impl Sb3Sound {
+    pub fn file_name(&self) -> Option<String> {
+        asset_file_name(&self.md5ext, &self.asset_id, &self.data_format)
+    }
+}
+
+/// 系统字体只有 `system` 与 `family`，自定义字体额外带有字体文件
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Font {
+    #[serde(default)]
+    pub system: String,
+    #[serde(default)]
+    pub family: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5ext: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_format: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+impl Sb3Font {
+    pub fn file_name(&self) -> Option<String> {
+        asset_file_name(&self.md5ext, &self.asset_id, &self.data_format)
+    }
+}
+
+/// 与 scratch-vm 一致：优先使用 `md5ext`，否则由 `assetId` 与 `dataFormat` 拼出文件名
+pub fn asset_file_name(
+    md5ext: &Option<String>,
+    asset_id: &Option<String>,
+    data_format: &Option<String>,
+) -> Option<String> {
+    match (md5ext, asset_id, data_format) {
+        (Some(md5ext), _, _) => Some(md5ext.clone()),
+        (None, Some(asset_id), Some(data_format)) => {
+            Some([asset_id, ".", &data_format.to_lowercase()].concat())
+        }
+        _ => None,
+    }
+}
+
+/// 缺少的字段由另一种写法推出：`md5ext` 拆成 `assetId` 与 `dataFormat`，反之亦然
+fn fill_asset_fields(
+    md5ext: &mut Option<String>,
+    asset_id: &mut Option<String>,
+    data_format: &mut Option<String>,
+) -> bool {
+    let mut changed = false;
+    if md5ext.is_none() {
+        *md5ext = asset_file_name(md5ext, asset_id, data_format);
+        changed |= md5ext.is_some();
+    }
+    if let Some((id, format)) = md5ext.as_deref().and_then(|m| m.rsplit_once('.')) {
+        if asset_id.is_none() {
+            *asset_id = Some(id.to_string());
+            changed = true;
+        }
+        if data_format.is_none() {
+            *data_format = Some(format.to_lowercase());
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Monitor {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub mode: String,
+    #[serde(default)]
+    pub opcode: String,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    #[serde(default)]
+    pub sprite_name: Option<String>,
+    #[serde(default)]
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<Number>,
+    #[serde(default)]
+    pub visible: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slider_min: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slider_max: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_discrete: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Sb3Meta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub semver: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vm: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const PROJECT_JSON: &str = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/project.json"
+    ));
+
+    fn round_trip(json: &str) -> Value {
+        let project = serde_json::from_str::<Sb3Project>(json).unwrap();
+        serde_json::from_slice(&project.to_json().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let original = serde_json::from_str::<Value>(PROJECT_JSON).unwrap();
+
+        assert_eq!(round_trip(PROJECT_JSON), original);
+    }
+
+    #[test]
+    fn missing_keys_get_vm_defaults() {
+        let json = r#"{
+            "targets": [{
+                "name": "Stage",
+                "blocks": { "a": { "opcode": "event_whenflagclicked" } }
+            }]
+        }"#;
+
+        let expected = json!({
+            "targets": [{
+                "isStage": false,
+                "name": "Stage",
+                "variables": {},
+                "lists": {},
+                "broadcasts": {},
+                "blocks": {
+                    "a": {
+                        "opcode": "event_whenflagclicked",
+                        "next": null,
+                        "parent": null,
+                        "inputs": {},
+                        "fields": {},
+                        "shadow": false,
+                        "topLevel": false
+                    }
+                },
+                "comments": {},
+                "costumes": [],
+                "sounds": []
+            }],
+            "monitors": [],
+            "extensions": []
+        });
+        assert_eq!(round_trip(json), expected);
+    }
+
+    #[test]
+    fn md5ext_only_costume_gets_asset_id_and_data_format() {
+        let json = r#"{
+            "targets": [{
+                "name": "Stage",
+                "costumes": [{ "name": "a", "md5ext": "cd21514d0531fdffb22204e0ec5ed84a.svg" }]
+            }]
+        }"#;
+        let mut project = serde_json::from_str::<Sb3Project>(json).unwrap();
+        let costume = &project.targets[0].costumes[0];
+        assert_eq!(
+            costume.file_name().as_deref(),
+            Some("cd21514d0531fdffb22204e0ec5ed84a.svg")
+        );
+
+        assert!(project.fill_md5ext());
+        let costume = &project.targets[0].costumes[0];
+        assert_eq!(
+            costume.asset_id.as_deref(),
+            Some("cd21514d0531fdffb22204e0ec5ed84a")
+        );
+        assert_eq!(costume.data_format.as_deref(), Some("svg"));
+        assert!(!project.fill_md5ext());
+    }
+
+    #[test]
+    fn asset_id_only_sound_gets_md5ext() {
+        let json = r#"{
+            "targets": [{
+                "name": "Stage",
+                "costumes": [{ "name": "a" }],
+                "sounds": [{
+                    "name": "b",
+                    "assetId": "83a9787d4cb6f3b7632b4ddfebf74367",
+                    "dataFormat": "WAV"
+                }]
+            }]
+        }"#;
+        let mut project = serde_json::from_str::<Sb3Project>(json).unwrap();
+        let sound = &project.targets[0].sounds[0];
+        assert_eq!(
+            sound.file_name().as_deref(),
+            Some("83a9787d4cb6f3b7632b4ddfebf74367.wav")
+        );
+        assert_eq!(project.targets[0].costumes[0].file_name(), None);
+
+        assert!(project.fill_md5ext());
+        let sound = &project.targets[0].sounds[0];
+        assert_eq!(
+            sound.md5ext.as_deref(),
+            Some("83a9787d4cb6f3b7632b4ddfebf74367.wav")
+        );
+        assert_eq!(sound.data_format.as_deref(), Some("WAV"));
+        assert_eq!(project.targets[0].costumes[0].md5ext, None);
+    }
+
+    #[test]
+    fn lenient_targets_variables_and_monitors() {
+        let json = r#"{
+            "targets": [{
+                "isStage": true,
+                "variables": { "a": ["v"] },
+                "lists": { "b": ["l"] }
+            }],
+            "monitors": [{ "opcode": "data_variable", "params": { "VARIABLE": "v" } }]
+        }"#;
+
+        let value = round_trip(json);
+        assert_eq!(value["targets"][0]["name"], json!(""));
+        assert_eq!(value["targets"][0]["variables"]["a"], json!(["v", 0]));
+        assert_eq!(value["targets"][0]["lists"]["b"], json!(["l", []]));
+        assert_eq!(value["monitors"][0]["id"], json!(""));
+    }
+}